@@ -0,0 +1,77 @@
+use crate::image::{Platform, PlatformParseError};
+use crate::pattern::Pattern;
+
+/// What is declared about an image in a Dockerfile or docker-compose file.
+///
+/// The pattern is given in an `# uptag --pattern "<pattern>"` comment.
+/// The required platforms can be appended to that comment as
+/// `--platform "linux/amd64,linux/arm64"`, or are taken from the
+/// `FROM --platform=<platform>` flag or the `platform` field of a service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub pattern: Pattern,
+    pub platforms: Vec<Platform>,
+}
+
+impl Annotation {
+    pub fn new(pattern: Pattern) -> Self {
+        Annotation {
+            pattern,
+            platforms: Vec::new(),
+        }
+    }
+
+    /// Adds `platforms`, skipping those that are already required.
+    pub fn with_platforms(mut self, platforms: impl IntoIterator<Item = Platform>) -> Self {
+        for platform in platforms {
+            if !self.platforms.contains(&platform) {
+                self.platforms.push(platform);
+            }
+        }
+        self
+    }
+}
+
+/// Parses a comma-separated list of platforms, e.g. `linux/amd64,linux/arm64/v8`.
+///
+/// Build arguments like `$BUILDPLATFORM` are skipped, since they are only known during the build.
+pub fn parse_platforms(raw: &str) -> Result<Vec<Platform>, PlatformParseError> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|platform| !platform.is_empty() && !platform.contains('$'))
+        .map(str::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_platform_list() {
+        assert_eq!(
+            parse_platforms("linux/amd64, linux/arm64/v8"),
+            Ok(vec![
+                "linux/amd64".parse().unwrap(),
+                "linux/arm64/v8".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn skips_build_arguments() {
+        assert_eq!(parse_platforms("$BUILDPLATFORM"), Ok(vec![]));
+    }
+
+    #[test]
+    fn merges_platforms() {
+        let pattern = Pattern::parse("<>").unwrap();
+        let annotation = Annotation::new(pattern)
+            .with_platforms(parse_platforms("linux/amd64").unwrap())
+            .with_platforms(parse_platforms("linux/amd64,linux/arm64").unwrap());
+        assert_eq!(
+            annotation.platforms,
+            parse_platforms("linux/amd64,linux/arm64").unwrap()
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::image::DOCKER_HUB;
+
+/// Credentials for logging in to a registry.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// The credentials stored by `docker login`.
+///
+/// This mirrors how Docker looks up credentials: A registry-specific helper
+/// in `credHelpers` takes precedence over the default helper in `credsStore`,
+/// which takes precedence over the base64-encoded entries in `auths`.
+///
+/// For details, see https://docs.docker.com/engine/reference/commandline/login/#credentials-store
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(rename = "credsStore")]
+    creds_store: Option<String>,
+    #[serde(rename = "credHelpers", default)]
+    cred_helpers: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+struct AuthEntry {
+    auth: Option<String>,
+}
+
+// The protocol of credential helpers
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperResponse {
+    username: String,
+    secret: String,
+}
+
+/// The key under which Docker stores credentials for DockerHub.
+const DOCKER_HUB_KEY: &str = "https://index.docker.io/v1/";
+
+impl DockerConfig {
+    /// Loads the configuration from `$DOCKER_CONFIG/config.json` or `~/.docker/config.json`.
+    ///
+    /// A missing configuration is treated as empty.
+    pub fn load() -> Result<Self, Error> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::from_path(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        let input = std::fs::read_to_string(path).map_err(|error| Error::Read {
+            path: path.display().to_string(),
+            source: error,
+        })?;
+        Self::parse(&input)
+    }
+
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        serde_json::from_str(input).map_err(Error::Parse)
+    }
+
+    fn default_path() -> Option<PathBuf> {
+        let directory = std::env::var_os("DOCKER_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".docker")))?;
+        Some(directory.join("config.json"))
+    }
+
+    /// Looks up the credentials for `registry`, e.g. `docker.io` or `ghcr.io`.
+    pub fn credentials_for(&self, registry: &str) -> Result<Option<Credentials>, Error> {
+        let server_url = if registry == DOCKER_HUB {
+            DOCKER_HUB_KEY
+        } else {
+            registry
+        };
+
+        let helper = self
+            .cred_helpers
+            .iter()
+            .find(|(key, _)| normalize_key(key) == normalize_key(server_url))
+            .map(|(_, helper)| helper)
+            .or(self.creds_store.as_ref());
+        if let Some(helper) = helper {
+            let program = format!("docker-credential-{}", helper);
+            if let Some(credentials) = run_helper(Command::new(&program), &program, server_url)? {
+                return Ok(Some(credentials));
+            }
+        }
+
+        self.auths
+            .iter()
+            .find(|(key, _)| normalize_key(key) == normalize_key(server_url))
+            .and_then(|(_, entry)| entry.auth.as_ref())
+            .map(|auth| decode_auth(auth))
+            .transpose()
+    }
+
+    /// Like `credentials_for`, but falls back to anonymous access if the lookup fails.
+    pub fn credentials_or_anonymous(&self, registry: &str) -> Option<Credentials> {
+        self.credentials_for(registry).unwrap_or_else(|error| {
+            log::warn!(
+                "{}. Accessing {} anonymously.",
+                crate::display_error(&error),
+                registry
+            );
+            None
+        })
+    }
+}
+
+/// Reduces keys like `https://index.docker.io/v1/` to the registry host.
+fn normalize_key(key: &str) -> &str {
+    let without_scheme = key
+        .strip_prefix("https://")
+        .or_else(|| key.strip_prefix("http://"))
+        .unwrap_or(key);
+    without_scheme.split('/').next().unwrap_or(without_scheme)
+}
+
+fn decode_auth(auth: &str) -> Result<Credentials, Error> {
+    let decoded = base64::decode(auth).map_err(|_| Error::InvalidAuth)?;
+    let decoded = String::from_utf8(decoded).map_err(|_| Error::InvalidAuth)?;
+    let (username, password) = decoded.split_once(':').ok_or(Error::InvalidAuth)?;
+    Ok(Credentials {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+/// Runs `<helper> get`, passing the server URL via stdin.
+///
+/// For details on the protocol, see https://github.com/docker/docker-credential-helpers
+fn run_helper(
+    mut command: Command,
+    program: &str,
+    server_url: &str,
+) -> Result<Option<Credentials>, Error> {
+    log::info!(
+        "Requesting credentials for {} from {}...",
+        server_url,
+        program
+    );
+    let helper_error = |source| Error::Helper {
+        helper: program.to_string(),
+        source,
+    };
+    let mut child = command
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(helper_error)?;
+    let written = child
+        .stdin
+        .take()
+        .unwrap() // We configured stdin to be piped.
+        .write_all(server_url.as_bytes());
+    match written {
+        // The helper may exit without reading its input.
+        Err(error) if error.kind() == std::io::ErrorKind::BrokenPipe => {}
+        result => result.map_err(helper_error)?,
+    }
+    let output = child.wait_with_output().map_err(helper_error)?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        // Helpers signal missing credentials with this message.
+        if message.contains("credentials not found") {
+            return Ok(None);
+        }
+        return Err(Error::HelperFailed {
+            helper: program.to_string(),
+            message,
+        });
+    }
+
+    let response: HelperResponse = serde_json::from_slice(&output.stdout).map_err(Error::Parse)?;
+    Ok(Some(Credentials {
+        username: response.username,
+        password: response.secret,
+    }))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read Docker config at `{path}`")]
+    Read {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse Docker credentials")]
+    Parse(#[source] serde_json::Error),
+    #[error(
+        "An `auth` entry in the Docker config is not valid base64-encoded `<username>:<password>`"
+    )]
+    InvalidAuth,
+    #[error("Failed to run credential helper `{helper}`")]
+    Helper {
+        helper: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Credential helper `{helper}` failed: {message}")]
+    HelperFailed { helper: String, message: String },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_auths() {
+        let config = DockerConfig::parse(
+            r#"{"auths": {"https://index.docker.io/v1/": {"auth": "dXNlcjpzZWNyZXQ="}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.credentials_for(DOCKER_HUB).unwrap(),
+            Some(Credentials {
+                username: "user".to_string(),
+                password: "secret".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn matches_registry_keys_with_scheme() {
+        let config =
+            DockerConfig::parse(r#"{"auths": {"https://ghcr.io": {"auth": "dXNlcjpzZWNyZXQ="}}}"#)
+                .unwrap();
+        assert!(config.credentials_for("ghcr.io").unwrap().is_some());
+        assert_eq!(config.credentials_for("quay.io").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_auth() {
+        let config =
+            DockerConfig::parse(r#"{"auths": {"ghcr.io": {"auth": "bm8gY29sb24="}}}"#).unwrap();
+        assert!(matches!(
+            config.credentials_for("ghcr.io"),
+            Err(Error::InvalidAuth)
+        ));
+    }
+
+    fn helper_script(directory: &tempfile::TempDir, script: &str) -> Command {
+        let path = directory.path().join("docker-credential-test");
+        std::fs::write(&path, script).unwrap();
+        let mut command = Command::new("sh");
+        command.arg(path);
+        command
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_credential_helper() {
+        let directory = tempfile::tempdir().unwrap();
+        let helper = helper_script(
+            &directory,
+            "read server\necho \"{\\\"ServerURL\\\":\\\"$server\\\",\\\"Username\\\":\\\"user\\\",\\\"Secret\\\":\\\"$server\\\"}\"\n",
+        );
+
+        let credentials = run_helper(helper, "test", "ghcr.io").unwrap();
+        assert_eq!(
+            credentials,
+            Some(Credentials {
+                username: "user".to_string(),
+                password: "ghcr.io".to_string()
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn treats_missing_helper_credentials_as_none() {
+        let directory = tempfile::tempdir().unwrap();
+        let helper = helper_script(
+            &directory,
+            "echo 'credentials not found in native keychain'\nexit 1\n",
+        );
+
+        assert_eq!(run_helper(helper, "test", "ghcr.io").unwrap(), None);
+    }
+}
@@ -5,7 +5,8 @@ use regex::Regex;
 use thiserror::Error;
 
 use crate::{
-    image::Image,
+    annotation::{self, Annotation},
+    image::{Image, PlatformParseError},
     pattern::{self, Pattern},
 };
 
@@ -57,7 +58,23 @@ pub fn parse(input: &str) -> Result<Vec<ServiceResult>, Error> {
                         pattern: raw_pattern.to_string(),
                         source: error,
                     })?;
-                BuildContext::Image(image, pattern)
+                let raw_platforms = captures
+                    .name("platforms")
+                    .map(|m| m.as_str())
+                    .into_iter()
+                    .chain(service.get_scalar("platform").map(|node| node.as_str()));
+                let mut annotation = Annotation::new(pattern);
+                for raw_platform in raw_platforms {
+                    let platforms = annotation::parse_platforms(raw_platform).map_err(|error| {
+                        Error::InvalidPlatform {
+                            service: service_name.to_string(),
+                            platform: raw_platform.to_string(),
+                            source: error,
+                        }
+                    })?;
+                    annotation = annotation.with_platforms(platforms);
+                }
+                BuildContext::Image(image, annotation)
             } else {
                 return Err(UnsupportedBuildContext {
                     service: service_name.to_string(),
@@ -68,7 +85,7 @@ pub fn parse(input: &str) -> Result<Vec<ServiceResult>, Error> {
         .collect()
 }
 
-type ServiceResult = (ServiceName, BuildContext<Pattern, PathBuf, ()>);
+type ServiceResult = (ServiceName, BuildContext<Annotation, PathBuf, ()>);
 
 #[derive(Debug, Error, PartialEq)]
 pub enum Error {
@@ -91,11 +108,18 @@ pub enum Error {
         #[source]
         source: pattern::Error,
     },
+    #[error("The platform `{platform}` for service `{service}` is invalid")]
+    InvalidPlatform {
+        service: String,
+        platform: String,
+        #[source]
+        source: PlatformParseError,
+    },
 }
 
 lazy_static! {
     static ref PATTERN: Regex =
-        Regex::new(r#"#\s*uptag\s+--pattern\s+"(?P<pattern>[^"]*)""#).unwrap();
+        Regex::new(r#"#\s*uptag\s+--pattern\s+"(?P<pattern>[^"]*)"(\s+--platform\s+"?(?P<platforms>[^"\s]+)"?)?"#).unwrap();
 }
 
 #[cfg(test)]
@@ -120,7 +144,7 @@ services:
                     "ubuntu".to_string(),
                     BuildContext::Image(
                         "ubuntu:18.04".parse().unwrap(),
-                        Pattern::parse("<!>.<>").unwrap()
+                        Annotation::new(Pattern::parse("<!>.<>").unwrap())
                     )
                 ),
                 (
@@ -131,6 +155,25 @@ services:
         )
     }
 
+    #[test]
+    fn parses_platforms() {
+        let input = r#"
+services:
+    ubuntu:
+        # uptag --pattern "<!>.<>" --platform linux/amd64
+        image: ubuntu:18.04
+        platform: linux/arm64
+        "#;
+        let services = parse(input).unwrap();
+        match &services[0].1 {
+            BuildContext::Image(_, annotation) => assert_eq!(
+                annotation.platforms,
+                annotation::parse_platforms("linux/amd64,linux/arm64").unwrap()
+            ),
+            other => panic!("Expected an image, but got {:?}", other),
+        }
+    }
+
     #[test]
     fn fails_when_services_is_missing() {
         let input = r#"
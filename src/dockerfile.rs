@@ -1,14 +1,15 @@
 use thiserror::Error;
 
-use crate::image::Image;
+use crate::annotation::{self, Annotation};
+use crate::image::{Image, Platform, PlatformParseError};
 use crate::pattern;
 use crate::pattern::Pattern;
 use matches::Matches;
 
-pub fn parse(input: &str) -> impl Iterator<Item = (Image, Result<Pattern, CheckError>)> + '_ {
+pub fn parse(input: &str) -> impl Iterator<Item = (Image, Result<Annotation, CheckError>)> + '_ {
     Matches::iter(input).map(|matches| {
         let image = matches.image();
-        let pattern = matches
+        let annotation = matches
             .pattern()
             .ok_or(CheckError::UnspecifiedPattern)
             .and_then(|m| {
@@ -16,11 +17,30 @@ pub fn parse(input: &str) -> impl Iterator<Item = (Image, Result<Pattern, CheckE
                     pattern: m.as_str().to_string(),
                     source: error,
                 })
+            })
+            .and_then(|pattern| {
+                let annotated = parse_platforms(matches.platforms())?;
+                let from_flag = parse_platforms(matches.from_platform())?;
+                Ok(Annotation::new(pattern)
+                    .with_platforms(annotated)
+                    .with_platforms(from_flag))
             });
-        (image, pattern)
+        (image, annotation)
     })
 }
 
+fn parse_platforms(raw: &Option<regex::Match>) -> Result<Vec<Platform>, CheckError> {
+    match raw {
+        Some(m) => {
+            annotation::parse_platforms(m.as_str()).map_err(|error| CheckError::InvalidPlatform {
+                platform: m.as_str().to_string(),
+                source: error,
+            })
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
 type Tag = String;
 
 #[derive(Debug, Error, PartialEq)]
@@ -35,6 +55,12 @@ pub enum CheckError {
         #[source]
         source: pattern::Error,
     },
+    #[error("The platform `{platform}` is invalid")]
+    InvalidPlatform {
+        platform: String,
+        #[source]
+        source: PlatformParseError,
+    },
 }
 
 pub mod matches {
@@ -48,50 +74,66 @@ pub mod matches {
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct Matches<'t> {
         all: regex::Match<'t>,
-        user: Option<regex::Match<'t>>,
-        image: regex::Match<'t>,
+        image: Image,
         tag: regex::Match<'t>,
         pattern: Option<regex::Match<'t>>,
+        platforms: Option<regex::Match<'t>>,
+        from_platform: Option<regex::Match<'t>>,
     }
 
     lazy_static! {
         static ref STATEMENT: Regex = Regex::new(
-            r#"(#\s*uptag\s+--pattern\s+"(?P<pattern>[^"]*)"\s*\n[\s\n]*)?\s*FROM\s*((?P<user>[[:word:]-.]+)/)?(?P<image>[[:word:]-.]+):(?P<tag>[[:word:][:punct:]]+)"#
+            r#"(#\s*uptag\s+--pattern\s+"(?P<pattern>[^"]*)"(\s+--platform\s+"?(?P<platforms>[^"\s]+)"?)?\s*\n[\s\n]*)?\s*FROM\s*(--platform=(?P<from_platform>\S+)\s+)?(?P<name>[[:word:]-./:]+):(?P<tag>[[:word:]][[:word:].-]*)(@(?P<digest>[[:word:]+.-]+:[[:xdigit:]]+))?(\s|$)"#
         ).unwrap();
     }
 
     impl<'t> Matches<'t> {
         pub fn first(dockerfile: &'t str) -> Option<Matches<'t>> {
-            STATEMENT.captures(dockerfile).map(Self::from_captures)
+            Self::iter(dockerfile).next()
         }
 
+        /// Iterates over all `FROM` statements.
+        ///
+        /// Statements whose image name is invalid are skipped.
         pub fn iter(dockerfile: &'t str) -> impl Iterator<Item = Matches<'t>> {
-            STATEMENT.captures_iter(dockerfile).map(Self::from_captures)
+            STATEMENT
+                .captures_iter(dockerfile)
+                .filter_map(Self::from_captures)
         }
 
-        fn from_captures(captures: regex::Captures<'t>) -> Matches<'t> {
-            Matches {
+        fn from_captures(captures: regex::Captures<'t>) -> Option<Matches<'t>> {
+            let tag = captures.name("tag").unwrap();
+            let image = Image {
+                name: ImageName::parse(&captures["name"])?,
+                tag: tag.as_str().to_string(),
+                digest: captures.name("digest").map(|m| m.as_str().to_string()),
+            };
+            Some(Matches {
                 all: captures.get(0).unwrap(),
-                user: captures.name("user"),
-                image: captures.name("image").unwrap(),
-                tag: captures.name("tag").unwrap(),
+                image,
+                tag,
                 pattern: captures.name("pattern"),
-            }
+                platforms: captures.name("platforms"),
+                from_platform: captures.name("from_platform"),
+            })
         }
 
         pub fn pattern(&self) -> &Option<regex::Match<'t>> {
             &self.pattern
         }
 
-        // TODO: Use image's parsing.
+        /// The platforms listed in the annotation via `--platform`.
+        pub fn platforms(&self) -> &Option<regex::Match<'t>> {
+            &self.platforms
+        }
+
+        /// The platform given by `FROM --platform=<platform>`.
+        pub fn from_platform(&self) -> &Option<regex::Match<'t>> {
+            &self.from_platform
+        }
+
         pub fn image(&self) -> Image {
-            Image {
-                name: ImageName::new(
-                    self.user.map(|m| m.as_str().to_string()),
-                    self.image.as_str().to_string(),
-                ),
-                tag: self.tag.as_str().to_string(),
-            }
+            self.image.clone()
         }
 
         pub fn extractor(&self) -> Option<Result<VersionExtractor, pattern::Error>> {
@@ -197,6 +239,62 @@ pub mod matches {
             )
         }
 
+        #[test]
+        fn matches_registry_image() {
+            let dockerfile = "FROM localhost:5000/team/app:3.10.5 AS build";
+            assert_eq_option!(
+                Matches::first(dockerfile),
+                Some(ExpectedMatches {
+                    image_name: ImageName::Registry {
+                        registry: "localhost:5000".into(),
+                        repository: "team/app".into()
+                    },
+                    image_tag: "3.10.5",
+                    extractor: None,
+                })
+            )
+        }
+
+        #[test]
+        fn treats_dotted_first_component_of_nested_name_as_registry() {
+            let dockerfile = "FROM weird.registry/team/weird.image:3.10.5";
+            assert_eq_option!(
+                Matches::first(dockerfile),
+                Some(ExpectedMatches {
+                    image_name: ImageName::Registry {
+                        registry: "weird.registry".into(),
+                        repository: "team/weird.image".into()
+                    },
+                    image_tag: "3.10.5",
+                    extractor: None,
+                })
+            )
+        }
+
+        #[test]
+        fn matches_platforms() {
+            let dockerfile = "# uptag --pattern \"<>\" --platform \"linux/amd64,linux/arm64\"\nFROM --platform=linux/arm/v7 ubuntu:14";
+            let matches = Matches::first(dockerfile).unwrap();
+            assert_eq!(
+                matches.platforms().map(|m| m.as_str()),
+                Some("linux/amd64,linux/arm64")
+            );
+            assert_eq!(
+                matches.from_platform().map(|m| m.as_str()),
+                Some("linux/arm/v7")
+            );
+            assert_eq!(matches.image(), "ubuntu:14".parse().unwrap());
+        }
+
+        #[test]
+        fn matches_pinned_digest() {
+            let digest = format!("sha256:{}", "a".repeat(64));
+            let dockerfile = format!("FROM ubuntu:20.04@{} AS build", digest);
+            let image = Matches::first(&dockerfile).unwrap().image();
+            assert_eq!(image.tag, "20.04");
+            assert_eq!(image.digest, Some(digest));
+        }
+
         #[test]
         fn does_not_match_empty_tag() {
             let dockerfile = "FROM ubuntu";
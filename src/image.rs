@@ -2,16 +2,18 @@ use std::fmt;
 
 use lazy_static::lazy_static;
 use regex::Regex;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 use thiserror::Error;
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Image {
     pub name: ImageName,
     pub tag: Tag,
+    pub digest: Option<Digest>,
 }
 
 pub type Tag = String;
+pub type Digest = String;
 
 impl std::str::FromStr for Image {
     type Err = ();
@@ -22,28 +24,31 @@ impl std::str::FromStr for Image {
             // The string contained extra character that do not belong in an image.
             return Err(());
         }
-        let user = captures.name("user").map(|m| m.as_str().to_string());
-        let image = captures.name("image").unwrap().as_str().to_string(); // An image is required for a match.
+        let name = ImageName::parse(&captures["name"]).ok_or(())?; // A name is required for a match.
         let tag = captures
             .name("tag")
             .map(|m| m.as_str().to_string())
             .unwrap_or_else(|| "latest".to_string());
-        Ok(Image {
-            name: ImageName::new(user, image),
-            tag,
-        })
+        let digest = captures.name("digest").map(|m| m.as_str().to_string());
+        Ok(Image { name, tag, digest })
     }
 }
 lazy_static! {
-    pub static ref IMAGE_REGEX: Regex = Regex::new(
-        r#"((?P<user>[[:word:]-]+)/)?(?P<image>[[:word:]-]+)(:(?P<tag>[[:word:][:punct:]]+))?"#
-    )
+    pub static ref IMAGE_REGEX: Regex = Regex::new(&format!(
+        r#"(?P<name>{name}){tag}"#,
+        name = REFERENCE_NAME,
+        tag = r#"(:(?P<tag>[[:word:]][[:word:].-]*))?(@(?P<digest>[A-Za-z][A-Za-z0-9]*([-_+.][A-Za-z][A-Za-z0-9]*)*:[0-9a-fA-F]{32,}))?"#
+    ))
     .unwrap();
 }
 
 impl fmt::Display for Image {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:{}", self.name, self.tag)
+        write!(f, "{}:{}", self.name, self.tag)?;
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
     }
 }
 
@@ -56,12 +61,29 @@ impl Serialize for Image {
     }
 }
 
+/// The name of an image, normalized like Docker does.
+///
+/// Images on DockerHub are represented as `Official` or `User`, so that
+/// `ubuntu`, `library/ubuntu` and `docker.io/library/ubuntu` all refer to
+/// the same image.
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum ImageName {
-    Official { image: String },
-    User { user: String, image: String },
+    Official {
+        image: String,
+    },
+    User {
+        user: String,
+        image: String,
+    },
+    /// An image on a registry other than DockerHub, e.g. `ghcr.io/org/team/app`.
+    Registry {
+        registry: String,
+        repository: String,
+    },
 }
 
+pub const DOCKER_HUB: &str = "docker.io";
+
 // "Name components may contain lowercase letters, digits and separators.
 // A separator is defined as a period, one or two underscores, or one or more dashes.
 // A name component may not start or end with a separator."
@@ -70,15 +92,17 @@ pub enum ImageName {
 // We will not check whether these restrictions are violated, because that would
 // make it unnecessarily complex. The consequence is that the image will not be found.
 // We will, however, allow only the specified character set.
-fn name_pattern() -> String {
-    let name_characters = r"[a-z0-9._-]+";
-    format!(
-        r"((?P<first>{name_chars})/)?(?P<second>{name_chars})",
-        name_chars = name_characters
-    )
-}
+//
+// The first component is a registry host (with an optional port) if it contains
+// a `:`, or is `localhost` or DockerHub's host. This follows Docker's normalization, see
+// https://github.com/distribution/distribution/blob/main/reference/normalize.go
+// Unlike Docker, a first component that only contains a `.` is a registry host only
+// if the repository is nested, since DockerHub user names like `weird.user` may
+// contain periods but DockerHub does not support nested repositories.
+const REFERENCE_NAME: &str = r"([a-zA-Z0-9.-]+(:[0-9]+)?/)?[a-z0-9._-]+(/[a-z0-9._-]+)*";
 lazy_static! {
-    static ref NAME: Regex = Regex::new(&format!("^{}$", name_pattern())).unwrap();
+    static ref NAME: Regex = Regex::new(&format!("^{}$", REFERENCE_NAME)).unwrap();
+    static ref REPOSITORY: Regex = Regex::new(r"^[a-z0-9._-]+(/[a-z0-9._-]+)*$").unwrap();
 }
 
 impl ImageName {
@@ -89,27 +113,85 @@ impl ImageName {
         }
     }
 
-    pub fn parse(image: &str) -> Option<ImageName> {
-        NAME.captures(image).map(|captures| {
-            let first = captures.name("first").map(|s| s.as_str().into());
-            let second = captures["second"].into(); // Second group is not optional, so access is safe.
-            match first {
-                Some(user) => ImageName::User {
-                    user,
-                    image: second,
-                },
-                None => ImageName::Official { image: second },
+    pub fn parse(name: &str) -> Option<ImageName> {
+        if !NAME.is_match(name) {
+            return None;
+        }
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((first, rest)) if is_registry(first, rest) => (first, rest),
+            _ => (DOCKER_HUB, name),
+        };
+        if !REPOSITORY.is_match(repository) {
+            return None;
+        }
+
+        Self::from_parts(registry, repository)
+    }
+
+    fn from_parts(registry: &str, repository: &str) -> Option<ImageName> {
+        if registry == DOCKER_HUB || registry == "index.docker.io" {
+            let components = repository.split('/').collect::<Vec<_>>();
+            match components.as_slice() {
+                [image] | ["library", image] => Some(ImageName::Official {
+                    image: image.to_string(),
+                }),
+                [user, image] => Some(ImageName::User {
+                    user: user.to_string(),
+                    image: image.to_string(),
+                }),
+                // DockerHub does not support nested repositories.
+                _ => None,
             }
-        })
+        } else {
+            Some(ImageName::Registry {
+                registry: registry.to_string(),
+                repository: repository.to_string(),
+            })
+        }
+    }
+
+    /// The host (and port) of the registry the image is hosted on, e.g. `docker.io` or `localhost:5000`.
+    pub fn registry(&self) -> &str {
+        match self {
+            ImageName::Official { .. } | ImageName::User { .. } => DOCKER_HUB,
+            ImageName::Registry { registry, .. } => registry,
+        }
+    }
+
+    /// The path of the image within its registry, e.g. `library/ubuntu`.
+    pub fn repository(&self) -> String {
+        use ImageName::*;
+        match self {
+            Official { image } => format!("library/{image}", image = image),
+            User { user, image } => format!("{user}/{image}", user = user, image = image),
+            Registry { repository, .. } => repository.clone(),
+        }
+    }
+
+    pub fn is_docker_hub(&self) -> bool {
+        !matches!(self, ImageName::Registry { .. })
     }
 }
 
+fn is_registry(first: &str, rest: &str) -> bool {
+    first.contains(':')
+        || first == "localhost"
+        || first == DOCKER_HUB
+        || first == "index.docker.io"
+        || (first.contains('.') && rest.contains('/'))
+}
+
 impl fmt::Display for ImageName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use ImageName::*;
         match self {
             Official { image } => write!(f, "{}", image),
             User { user, image } => write!(f, "{}/{}", user, image),
+            Registry {
+                registry,
+                repository,
+            } => write!(f, "{}/{}", registry, repository),
         }
     }
 }
@@ -124,11 +206,73 @@ impl std::str::FromStr for ImageName {
 }
 
 #[derive(Debug, Error)]
-#[error("`{invalid}` is not a valid name of the form `<image>` or `<user>/<image>`")]
+#[error("`{invalid}` is not a valid name of the form `<image>`, `<user>/<image>` or `<registry>/<repository>`")]
 pub struct ParseError {
     invalid: String,
 }
 
+/// An operating system and CPU architecture an image supports, e.g. `linux/arm64/v8`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+impl Platform {
+    /// Whether an image built for `available` runs on this platform.
+    ///
+    /// A platform without a variant accepts any variant, so that `linux/arm64` is satisfied by `linux/arm64/v8`.
+    pub fn is_satisfied_by(&self, available: &Platform) -> bool {
+        self.os == available.os
+            && self.architecture == available.architecture
+            && (self.variant.is_none() || self.variant == available.variant)
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Platform {
+    type Err = PlatformParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || PlatformParseError {
+            invalid: s.to_string(),
+        };
+        let parts = s.split('/').collect::<Vec<_>>();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(error());
+        }
+        match parts.as_slice() {
+            [os, architecture] => Ok(Platform {
+                os: os.to_string(),
+                architecture: architecture.to_string(),
+                variant: None,
+            }),
+            [os, architecture, variant] => Ok(Platform {
+                os: os.to_string(),
+                architecture: architecture.to_string(),
+                variant: Some(variant.to_string()),
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("`{invalid}` is not a valid platform of the form `<os>/<architecture>` or `<os>/<architecture>/<variant>`")]
+pub struct PlatformParseError {
+    invalid: String,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -156,11 +300,107 @@ mod test {
             "ubuntu:14.04".parse(),
             Ok(Image {
                 name: ImageName::new(None, "ubuntu".to_string()),
-                tag: "14.04".to_string()
+                tag: "14.04".to_string(),
+                digest: None,
+            })
+        )
+    }
+
+    #[test]
+    fn parses_registry_image() {
+        assert_eq!(
+            "ghcr.io/org/team/app:1.2".parse(),
+            Ok(Image {
+                name: ImageName::Registry {
+                    registry: "ghcr.io".to_string(),
+                    repository: "org/team/app".to_string()
+                },
+                tag: "1.2".to_string(),
+                digest: None,
+            })
+        )
+    }
+
+    #[test]
+    fn parses_registry_with_port() {
+        assert_eq!(
+            "localhost:5000/foo:3".parse(),
+            Ok(Image {
+                name: ImageName::Registry {
+                    registry: "localhost:5000".to_string(),
+                    repository: "foo".to_string()
+                },
+                tag: "3".to_string(),
+                digest: None,
+            })
+        )
+    }
+
+    #[test]
+    fn parses_digest() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        assert_eq!(
+            format!("ubuntu:20.04@{}", digest).parse(),
+            Ok(Image {
+                name: ImageName::new(None, "ubuntu".to_string()),
+                tag: "20.04".to_string(),
+                digest: Some(digest),
             })
         )
     }
 
+    #[test]
+    fn normalizes_docker_hub_names() {
+        let official = ImageName::new(None, "ubuntu".to_string());
+        assert_eq!(ImageName::parse("library/ubuntu"), Some(official.clone()));
+        assert_eq!(
+            ImageName::parse("docker.io/library/ubuntu"),
+            Some(official.clone())
+        );
+        assert_eq!(ImageName::parse("index.docker.io/ubuntu"), Some(official));
+        assert_eq!(
+            ImageName::parse("docker.io/y0hy0h/uptag"),
+            Some(ImageName::new(
+                Some("y0hy0h".to_string()),
+                "uptag".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn determines_registry_and_repository() {
+        let official = ImageName::new(None, "ubuntu".to_string());
+        assert_eq!(official.registry(), "docker.io");
+        assert_eq!(official.repository(), "library/ubuntu");
+
+        let registry = ImageName::parse("registry.gitlab.com/group/project").unwrap();
+        assert_eq!(registry.registry(), "registry.gitlab.com");
+        assert_eq!(registry.repository(), "group/project");
+    }
+
+    #[test]
+    fn parses_platform() {
+        assert_eq!(
+            "linux/arm64/v8".parse(),
+            Ok(Platform {
+                os: "linux".to_string(),
+                architecture: "arm64".to_string(),
+                variant: Some("v8".to_string()),
+            })
+        );
+        assert!("linux".parse::<Platform>().is_err());
+        assert!("linux//v8".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn matches_any_variant_if_unspecified() {
+        let arm64 = "linux/arm64".parse::<Platform>().unwrap();
+        let arm64_v8 = "linux/arm64/v8".parse::<Platform>().unwrap();
+        assert!(arm64.is_satisfied_by(&arm64_v8));
+        assert!(!arm64_v8.is_satisfied_by(&arm64));
+        assert!(!arm64.is_satisfied_by(&"linux/amd64".parse().unwrap()));
+    }
+
     #[test]
     fn rejects_invalid_image() {
         assert_eq!("i/am/invalid".parse::<Image>(), Err(()))
@@ -170,4 +410,9 @@ mod test {
     fn rejects_invalid_name() {
         assert_eq!(ImageName::parse("i/am/invalid"), None);
     }
+
+    #[test]
+    fn rejects_uppercase_name() {
+        assert_eq!(ImageName::parse("Ubuntu"), None);
+    }
 }
@@ -0,0 +1,74 @@
+//! Reading and writing the JSON files that uptag keeps between runs, like lockfiles and snapshots.
+
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+/// Loads the file at `path`, treating a missing file as the default value.
+///
+/// `kind` names what the file holds in errors, e.g. `lockfile`.
+pub fn load_or_default<T>(path: &Path, kind: &'static str) -> Result<T, Error>
+where
+    T: DeserializeOwned + Default,
+{
+    if path.exists() {
+        from_path(path, kind)
+    } else {
+        Ok(T::default())
+    }
+}
+
+pub fn from_path<T>(path: &Path, kind: &'static str) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let input = std::fs::read_to_string(path).map_err(|error| Error::Read {
+        kind,
+        path: path.display().to_string(),
+        source: error,
+    })?;
+    serde_json::from_str(&input).map_err(|error| Error::Parse {
+        kind,
+        path: path.display().to_string(),
+        source: error,
+    })
+}
+
+pub fn save<T>(value: &T, path: &Path, kind: &'static str) -> Result<(), Error>
+where
+    T: Serialize,
+{
+    let output = serde_json::to_string_pretty(value).unwrap(); // Serializing strings cannot fail.
+    std::fs::write(path, output).map_err(|error| Error::Write {
+        kind,
+        path: path.display().to_string(),
+        source: error,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Failed to read {kind} `{path}`")]
+    Read {
+        kind: &'static str,
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse {kind} `{path}`")]
+    Parse {
+        kind: &'static str,
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to write {kind} `{path}`")]
+    Write {
+        kind: &'static str,
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
@@ -1,18 +1,29 @@
+pub mod annotation;
+pub mod credentials;
 pub mod docker_compose;
 pub mod dockerfile;
 pub mod image;
+pub mod json_file;
+pub mod lockfile;
 pub mod pattern;
 pub mod report;
 pub mod tag_fetcher;
 pub mod version;
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use image::Image;
-use tag_fetcher::TagFetcher;
+use image::{Digest, Image, Platform};
+#[cfg(feature = "async")]
+use tag_fetcher::asynchronous::AsyncTagFetcher;
+use tag_fetcher::{TagFetcher, TagRecord};
 use version::extractor::VersionExtractor;
-use version::UpdateType;
+use version::{Grade, UpdateType, Version};
 
 pub fn find_update<T>(
     fetcher: &T,
@@ -22,65 +33,712 @@ pub fn find_update<T>(
 where
     T: TagFetcher,
 {
-    let current_tag = &image.tag;
-    let current_version =
-        extractor
-            .extract_from(&image.tag)
-            .ok_or(FindUpdateError::CurrentTagPatternConflict {
-                current_tag: image.tag.to_string(),
-                pattern: extractor.pattern().to_string(),
-            })?;
+    find_update_with_options(fetcher, image, extractor, &SearchOptions::default())
+}
 
-    let mut breaking_update = None;
+/// Restricts which tags `find_update_with_options` proposes as updates.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchOptions {
+    platforms: Vec<Platform>,
+    recorded_digest: Option<Digest>,
+    order: SearchOrder,
+    adaptive: bool,
+    time_budget: Option<Duration>,
+    pre_releases: bool,
+    per_grade: bool,
+}
 
-    let mut searched_amount = 0;
-    for tag_result in fetcher.fetch(&image.name) {
-        searched_amount += 1;
+/// How `find_update_with_options` selects updates among the fetched tags.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOrder {
+    /// Relies on the tags being fetched from newest to oldest, stopping at the
+    /// current tag or at the first compatible update.
+    #[default]
+    Chronological,
+    /// Searches all fetched tags and selects the highest compatible and breaking
+    /// versions, no matter when they were pushed.
+    ///
+    /// Use this if old tags are pushed again, e.g. when rebuilding them.
+    Version,
+}
 
-        let tag_candidate = tag_result?;
+impl std::str::FromStr for SearchOrder {
+    type Err = String;
 
-        if &tag_candidate == current_tag {
-            return Ok(Update {
-                compatible: None,
-                breaking: breaking_update,
-            });
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chronological" => Ok(SearchOrder::Chronological),
+            "version" => Ok(SearchOrder::Version),
+            _ => Err(format!(
+                "`{}` is not one of the orders `chronological` or `version`",
+                s
+            )),
         }
+    }
+}
+
+impl SearchOptions {
+    /// Only proposes tags that are published for all of the `platforms`.
+    ///
+    /// Tags without platform metadata are not skipped, since their platforms are unknown.
+    pub fn with_platforms(mut self, platforms: Vec<Platform>) -> Self {
+        self.platforms = platforms;
+        self
+    }
+
+    pub fn platforms(&self) -> &[Platform] {
+        &self.platforms
+    }
+
+    /// Reports the current tag as rebuilt if it no longer points to `digest`, e.g. the digest stored in a lockfile.
+    ///
+    /// The digest the image is pinned to takes precedence.
+    pub fn with_recorded_digest(mut self, digest: Option<Digest>) -> Self {
+        self.recorded_digest = digest;
+        self
+    }
+
+    pub fn recorded_digest(&self) -> Option<&Digest> {
+        self.recorded_digest.as_ref()
+    }
+
+    pub fn with_order(mut self, order: SearchOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn order(&self) -> SearchOrder {
+        self.order
+    }
+
+    /// Stops the search at the first tag with a lower version than the current tag, as if the current tag was encountered.
+    ///
+    /// This allows fetching with a search limit that is large enough for images with
+    /// many tags, since the search usually ends long before the limit is reached.
+    /// Only applies to `SearchOrder::Chronological`.
+    pub fn with_adaptive_limit(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    pub fn adaptive_limit(&self) -> bool {
+        self.adaptive
+    }
+
+    /// Stops the search after `time_budget`, as if there were no more tags.
+    pub fn with_time_budget(mut self, time_budget: Option<Duration>) -> Self {
+        self.time_budget = time_budget;
+        self
+    }
+
+    pub fn time_budget(&self) -> Option<Duration> {
+        self.time_budget
+    }
+
+    /// Proposes pre-releases like `3.10.0rc2` as updates, which are skipped by default.
+    ///
+    /// Only applies to patterns with a `<pre>` placeholder.
+    pub fn with_pre_releases(mut self, pre_releases: bool) -> Self {
+        self.pre_releases = pre_releases;
+        self
+    }
+
+    pub fn pre_releases(&self) -> bool {
+        self.pre_releases
+    }
 
-        if let Some(version_candidate) = extractor.extract_from(&tag_candidate) {
-            if version_candidate < current_version {
-                continue;
+    /// Also proposes the latest update of each grade, e.g. the latest patch, minor and major update, in `Update::per_grade`.
+    ///
+    /// With `SearchOrder::Chronological`, this searches up to the current tag instead of stopping at the first compatible update.
+    pub fn with_updates_per_grade(mut self, per_grade: bool) -> Self {
+        self.per_grade = per_grade;
+        self
+    }
+
+    pub fn updates_per_grade(&self) -> bool {
+        self.per_grade
+    }
+
+    fn is_out_of_time(&self, started: Instant, image: &Image) -> bool {
+        match self.time_budget {
+            Some(budget) if started.elapsed() > budget => {
+                log::warn!(
+                    "Stopped searching the tags of {} after {}s.",
+                    image.name,
+                    budget.as_secs()
+                );
+                true
             }
+            _ => false,
+        }
+    }
 
-            match version_candidate
-                .update_type(&current_version, extractor.pattern().breaking_degree())
-            {
-                UpdateType::Breaking => {
-                    breaking_update = breaking_update.or(Some(tag_candidate));
-                }
-                UpdateType::Compatible => {
-                    return Ok(Update {
-                        compatible: Some(tag_candidate),
-                        breaking: breaking_update,
-                    })
+    fn accepts(&self, candidate: &TagRecord) -> bool {
+        candidate.platforms.is_empty()
+            || self.platforms.iter().all(|required| {
+                candidate
+                    .platforms
+                    .iter()
+                    .any(|available| required.is_satisfied_by(available))
+            })
+    }
+}
+
+/// Finds the updates of `image` among the tags that `options` accepts.
+///
+/// If the image is pinned to a digest, the digests of the updates are resolved as well,
+/// so that they can be pinned, too.
+///
+/// If the image is pinned to a digest or `options` has a recorded digest, the current tag
+/// is reported as rebuilt if the registry's digest for it differs.
+pub fn find_update_with_options<T>(
+    fetcher: &T,
+    image: &Image,
+    extractor: &VersionExtractor,
+    options: &SearchOptions,
+) -> Result<Update, FindUpdateError<T::FetchError>>
+where
+    T: TagFetcher,
+{
+    let by_version;
+    let options = if options.order == SearchOrder::Chronological
+        && !fetcher.lists_newest_first(&image.name)
+    {
+        log::info!(
+            "Searching the tags of {} by version, since the registry does not list them by age.",
+            image.name
+        );
+        by_version = options.clone().with_order(SearchOrder::Version);
+        &by_version
+    } else {
+        options
+    };
+    let mut search = Search::new(image, extractor, options)?;
+    for tag_result in fetch_candidates(fetcher, image, extractor) {
+        if search.is_out_of_time() || search.offer(tag_result?) {
+            break;
+        }
+    }
+    let unresolved = Unresolved::new(image, options, search.finish()?);
+
+    let mut digests = HashMap::new();
+    for tag in unresolved.missing_digests() {
+        let digest = fetcher.digest(&image.name, &tag)?;
+        digests.insert(tag, digest);
+    }
+    Ok(unresolved.resolve(&digests))
+}
+
+/// Fetches the `image`'s tags, allowing the `fetcher` to skip those lacking the pattern's longest literal.
+fn fetch_candidates<T>(fetcher: &T, image: &Image, extractor: &VersionExtractor) -> T::TagIter
+where
+    T: TagFetcher,
+{
+    match extractor.pattern().longest_literal() {
+        Some(literal) => fetcher.fetch_containing(&image.name, literal),
+        None => fetcher.fetch(&image.name),
+    }
+}
+
+/// Like `find_update`, but fetches the tags without blocking, e.g. within a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn find_update_async<T>(
+    fetcher: &T,
+    image: &Image,
+    extractor: &VersionExtractor,
+) -> Result<Update, FindUpdateError<T::FetchError>>
+where
+    T: AsyncTagFetcher,
+{
+    find_update_with_options_async(fetcher, image, extractor, &SearchOptions::default()).await
+}
+
+/// Like `find_update_with_options`, but fetches the tags without blocking, e.g. within a tokio runtime.
+#[cfg(feature = "async")]
+pub async fn find_update_with_options_async<T>(
+    fetcher: &T,
+    image: &Image,
+    extractor: &VersionExtractor,
+    options: &SearchOptions,
+) -> Result<Update, FindUpdateError<T::FetchError>>
+where
+    T: AsyncTagFetcher,
+{
+    use futures_util::StreamExt;
+
+    let mut search = Search::new(image, extractor, options)?;
+    let mut tags = match extractor.pattern().longest_literal() {
+        Some(literal) => fetcher.fetch_containing(&image.name, literal),
+        None => fetcher.fetch(&image.name),
+    };
+    while let Some(tag_result) = tags.next().await {
+        if search.is_out_of_time() || search.offer(tag_result?) {
+            break;
+        }
+    }
+    let unresolved = Unresolved::new(image, options, search.finish()?);
+
+    let mut digests = HashMap::new();
+    for tag in unresolved.missing_digests() {
+        let digest = fetcher.digest(&image.name, &tag).await?;
+        digests.insert(tag, digest);
+    }
+    Ok(unresolved.resolve(&digests))
+}
+
+/// An update whose digests are yet to be looked up by `find_update_with_options` or its async counterpart.
+struct Unresolved<'a> {
+    image: &'a Image,
+    /// The digest that the current tag is checked against for rebuilds.
+    recorded_digest: Option<&'a Digest>,
+    update: Update,
+    current: TagRecord,
+}
+
+impl<'a> Unresolved<'a> {
+    fn new(
+        image: &'a Image,
+        options: &'a SearchOptions,
+        (update, current): (Update, Option<TagRecord>),
+    ) -> Self {
+        Unresolved {
+            image,
+            recorded_digest: image.digest.as_ref().or_else(|| options.recorded_digest()),
+            update,
+            current: current.unwrap_or_else(|| TagRecord::from(image.tag.as_str())),
+        }
+    }
+
+    /// The tags whose digests are needed: the current tag's to check for rebuilds,
+    /// and the updates' if the image is pinned to a digest.
+    fn missing_digests(&self) -> Vec<Tag> {
+        let mut tags = Vec::new();
+        if self.recorded_digest.is_some() && self.current.digest.is_none() {
+            tags.push(self.current.name.clone());
+        }
+        if self.image.digest.is_some() {
+            for record in self.update.records() {
+                if record.digest.is_none() && !tags.contains(&record.name) {
+                    tags.push(record.name.clone());
                 }
             }
         }
+        tags
     }
 
-    if breaking_update.is_some() {
-        Ok(Update {
+    /// Fills in the looked up `digests` and checks the current tag for rebuilds.
+    fn resolve(self, digests: &HashMap<Tag, Option<Digest>>) -> Update {
+        let Unresolved {
+            image,
+            recorded_digest,
+            mut update,
+            mut current,
+        } = self;
+        let fill_in = |record: &mut TagRecord| {
+            if record.digest.is_none() {
+                record.digest = digests.get(&record.name).cloned().flatten();
+            }
+        };
+
+        if let Some(recorded_digest) = recorded_digest {
+            fill_in(&mut current);
+            update.check_rebuilt(image, current, recorded_digest);
+        }
+        if image.digest.is_some() {
+            update.records_mut().for_each(fill_in);
+        }
+        update
+    }
+}
+
+/// Searches the fetched tags for updates, one tag at a time.
+struct Search<'a> {
+    image: &'a Image,
+    extractor: &'a VersionExtractor,
+    options: &'a SearchOptions,
+    current_version: Version,
+    /// The values of the current tag's pinned wildcards, which updates have to keep.
+    pinned_values: Option<Vec<Option<String>>>,
+    started: Instant,
+    searched_amount: usize,
+    /// The current tag's record, if it was encountered.
+    current: Option<TagRecord>,
+    compatible: Option<(Version, TagRecord)>,
+    breaking: Option<(Version, TagRecord)>,
+    /// The latest update of each grade, if `options` asks for them.
+    per_grade: Vec<(Version, GradedUpdate)>,
+    /// Whether the remaining tags were skipped, because they cannot change the result.
+    finished: bool,
+    /// Whether a tag without platforms was accepted despite required platforms, which is only logged once.
+    warned_about_platforms: bool,
+}
+
+impl<'a> Search<'a> {
+    fn new<E>(
+        image: &'a Image,
+        extractor: &'a VersionExtractor,
+        options: &'a SearchOptions,
+    ) -> Result<Self, FindUpdateError<E>>
+    where
+        E: 'static + std::error::Error,
+    {
+        let current_version = extractor.extract_from(&image.tag).ok_or(
+            FindUpdateError::CurrentTagPatternConflict {
+                current_tag: image.tag.to_string(),
+                pattern: extractor.pattern().to_string(),
+            },
+        )?;
+        Ok(Search {
+            image,
+            extractor,
+            options,
+            current_version,
+            pinned_values: extractor.pinned_values(&image.tag),
+            started: Instant::now(),
+            searched_amount: 0,
+            current: None,
             compatible: None,
-            breaking: breaking_update,
+            breaking: None,
+            per_grade: Vec::new(),
+            finished: false,
+            warned_about_platforms: false,
         })
-    } else {
-        Err(FindUpdateError::CurrentTagNotEncountered { searched_amount })
+    }
+
+    fn is_out_of_time(&self) -> bool {
+        self.options.is_out_of_time(self.started, self.image)
+    }
+
+    /// Searches the next fetched tag, returning whether the remaining tags can be skipped.
+    fn offer(&mut self, tag_candidate: TagRecord) -> bool {
+        self.searched_amount += 1;
+        self.finished = match self.options.order {
+            SearchOrder::Chronological => self.offer_chronologically(tag_candidate),
+            SearchOrder::Version => {
+                self.offer_by_version(tag_candidate);
+                false
+            }
+        };
+        self.finished
+    }
+
+    /// Stops at the current tag or at the first compatible update, unless updates per grade are searched.
+    fn offer_chronologically(&mut self, tag_candidate: TagRecord) -> bool {
+        if tag_candidate.name == self.image.tag {
+            self.current = Some(tag_candidate);
+            return true;
+        }
+
+        let version_candidate = match self.extractor.extract_from(&tag_candidate) {
+            Some(version) => version,
+            None => return false,
+        };
+        // Tags that only differ in wildcards, omitted parts or alternative spellings are not updates.
+        if version_candidate == self.current_version {
+            return false;
+        }
+        if version_candidate < self.current_version {
+            if self.options.adaptive {
+                log::info!(
+                    "Stopping at `{}`, because it is older than the current tag.",
+                    tag_candidate
+                );
+                return true;
+            }
+            return false;
+        }
+
+        if !self.accepts(&tag_candidate, &version_candidate) {
+            return false;
+        }
+
+        let update_type = version_candidate.update_type(
+            &self.current_version,
+            self.extractor.pattern().breaking_degree(),
+        );
+        self.keep_per_grade(&version_candidate, &tag_candidate, update_type);
+        match update_type {
+            UpdateType::Breaking => {
+                if self.breaking.is_none() {
+                    self.breaking = Some((version_candidate, tag_candidate));
+                }
+                false
+            }
+            UpdateType::Compatible => {
+                if self.compatible.is_none() {
+                    self.compatible = Some((version_candidate, tag_candidate));
+                }
+                !self.options.per_grade
+            }
+        }
+    }
+
+    /// Keeps the highest compatible and breaking versions.
+    fn offer_by_version(&mut self, tag_candidate: TagRecord) {
+        if tag_candidate.name == self.image.tag {
+            if self.current.is_none() {
+                self.current = Some(tag_candidate);
+            }
+            return;
+        }
+
+        let version_candidate = match self.extractor.extract_from(&tag_candidate) {
+            Some(version) if version > self.current_version => version,
+            _ => return,
+        };
+
+        if !self.accepts(&tag_candidate, &version_candidate) {
+            return;
+        }
+
+        let update_type = version_candidate.update_type(
+            &self.current_version,
+            self.extractor.pattern().breaking_degree(),
+        );
+        self.keep_per_grade(&version_candidate, &tag_candidate, update_type);
+        let best = match update_type {
+            UpdateType::Breaking => &mut self.breaking,
+            UpdateType::Compatible => &mut self.compatible,
+        };
+        // On equal versions, the tag fetched first is kept, since it is likely the newest.
+        let improves = match best {
+            Some((version, _)) => version_candidate > *version,
+            None => true,
+        };
+        if improves {
+            *best = Some((version_candidate, tag_candidate));
+        }
+    }
+
+    /// Keeps the first update of each grade, or the highest with `SearchOrder::Version`.
+    fn keep_per_grade(&mut self, version: &Version, record: &TagRecord, update_type: UpdateType) {
+        if !self.options.per_grade {
+            return;
+        }
+        let (group, grade) = match self.describe(version) {
+            (group, Some(grade)) => (group, grade),
+            (_, None) => return,
+        };
+        let kept = self
+            .per_grade
+            .iter_mut()
+            .find(|(_, kept)| kept.group == group && kept.grade == grade);
+        let graded = GradedUpdate {
+            record: record.clone(),
+            update_type,
+            group,
+            grade,
+        };
+        match kept {
+            None => self.per_grade.push((version.clone(), graded)),
+            // On equal versions, the tag fetched first is kept, since it is likely the newest.
+            Some(kept) if self.options.order == SearchOrder::Version && *version > kept.0 => {
+                *kept = (version.clone(), graded)
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// The named version group that changed in the `version` and the grade of the change.
+    fn describe(&self, version: &Version) -> (Option<String>, Option<Grade>) {
+        let breaking_degree = self.extractor.pattern().breaking_degree();
+        let group = version
+            .updated_group(&self.current_version, breaking_degree)
+            .map(str::to_string);
+        let grade = version.grade(&self.current_version, breaking_degree);
+        (group, grade)
+    }
+
+    fn accepts(&mut self, tag_candidate: &TagRecord, version_candidate: &Version) -> bool {
+        if version_candidate.is_pre_release() && !self.options.pre_releases {
+            log::info!("Skipping `{}`, because it is a pre-release.", tag_candidate);
+            return false;
+        }
+        if let Some(group) = version_candidate.downgraded_group(&self.current_version) {
+            log::info!(
+                "Skipping `{}`, because its {} version is lower than the current tag's.",
+                tag_candidate,
+                group
+            );
+            return false;
+        }
+        if self.extractor.pinned_values(tag_candidate) != self.pinned_values {
+            log::info!(
+                "Skipping `{}`, because its pinned parts differ from the current tag.",
+                tag_candidate
+            );
+            return false;
+        }
+        if tag_candidate.platforms.is_empty()
+            && !self.options.platforms.is_empty()
+            && !self.warned_about_platforms
+        {
+            log::warn!(
+                "The registry does not report platforms for {}, so its tags are not checked for the required platforms.",
+                self.image.name
+            );
+            self.warned_about_platforms = true;
+        }
+        let accepted = self.options.accepts(tag_candidate);
+        if !accepted {
+            log::info!(
+                "Skipping `{}`, because it is not published for all required platforms.",
+                tag_candidate
+            );
+        }
+        accepted
+    }
+
+    /// Returns the updates, also returning the current tag's record if it was encountered.
+    fn finish<E>(self) -> Result<(Update, Option<TagRecord>), FindUpdateError<E>>
+    where
+        E: 'static + std::error::Error,
+    {
+        let found = self.current.is_some() || self.compatible.is_some() || self.breaking.is_some();
+        if !found && !self.finished {
+            return Err(FindUpdateError::CurrentTagNotEncountered {
+                searched_amount: self.searched_amount,
+            });
+        }
+
+        let describe = |update: &Option<(Version, TagRecord)>| match update {
+            Some((version, _)) => self.describe(version),
+            None => (None, None),
+        };
+        let (compatible_group, compatible_grade) = describe(&self.compatible);
+        let (breaking_group, breaking_grade) = describe(&self.breaking);
+        let mut per_grade = self
+            .per_grade
+            .into_iter()
+            .map(|(_, graded)| graded)
+            .collect::<Vec<_>>();
+        // The safest steps come first.
+        per_grade.sort_by_key(|graded| (graded.update_type, std::cmp::Reverse(graded.grade)));
+        let update = Update {
+            compatible: self.compatible.map(|(_, record)| record),
+            breaking: self.breaking.map(|(_, record)| record),
+            rebuilt: None,
+            searched_amount: self.searched_amount,
+            compatible_group,
+            breaking_group,
+            compatible_grade,
+            breaking_grade,
+            per_grade,
+        };
+        Ok((update, self.current))
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+/// Runs `find_update` for all `jobs`, checking up to `concurrency` images at the same time.
+///
+/// The results are in the same order as the `jobs`, no matter which check finishes first.
+/// `on_finished` is called with the index of each job as soon as it finishes.
+pub fn find_updates<T>(
+    fetcher: &T,
+    jobs: &[(Image, VersionExtractor, SearchOptions)],
+    concurrency: usize,
+    on_finished: impl Fn(usize) + Sync,
+) -> Vec<Result<Update, FindUpdateError<T::FetchError>>>
+where
+    T: TagFetcher + Sync,
+    T::FetchError: Send,
+{
+    let next_job = AtomicUsize::new(0);
+    let results = jobs.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.max(1).min(jobs.len()) {
+            scope.spawn(|| loop {
+                let index = next_job.fetch_add(1, Ordering::SeqCst);
+                let (image, extractor, options) = match jobs.get(index) {
+                    Some(job) => job,
+                    None => break,
+                };
+                let result = find_update_with_options(fetcher, image, extractor, options);
+                *results[index].lock().unwrap() = Some(result);
+                on_finished(index);
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap().unwrap()) // Every job was run exactly once.
+        .collect()
+}
+
+/// The updates found for an image.
+///
+/// More fields may be added, so other crates start from `Update::default()` and set the fields they need.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[non_exhaustive]
 pub struct Update {
-    pub compatible: Option<Tag>,
-    pub breaking: Option<Tag>,
+    pub compatible: Option<TagRecord>,
+    pub breaking: Option<TagRecord>,
+    /// The current tag with its new digest, if it was pushed again since its digest was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebuilt: Option<TagRecord>,
+    /// How many tags were searched to find the updates.
+    #[serde(default)]
+    pub searched_amount: usize,
+    /// The named version group whose change made `compatible` an update, or `None` if the version parts outside of named groups changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compatible_group: Option<String>,
+    /// The named version group whose change made `breaking` a breaking update, like `compatible_group`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breaking_group: Option<String>,
+    /// How far the `compatible` update reaches, e.g. a minor or a patch update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compatible_grade: Option<Grade>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breaking_grade: Option<Grade>,
+    /// The latest update of each grade, safest first, if requested with `SearchOptions::with_updates_per_grade`.
+    ///
+    /// Includes the `compatible` and `breaking` updates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub per_grade: Vec<GradedUpdate>,
+}
+
+/// The latest update of a grade, see `SearchOptions::with_updates_per_grade`.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct GradedUpdate {
+    pub record: TagRecord,
+    pub update_type: UpdateType,
+    /// The named version group that changed, like `Update::compatible_group`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub grade: Grade,
+}
+
+impl Update {
+    /// The records of all proposed updates.
+    fn records(&self) -> impl Iterator<Item = &TagRecord> {
+        self.compatible
+            .iter()
+            .chain(self.breaking.iter())
+            .chain(self.per_grade.iter().map(|graded| &graded.record))
+    }
+
+    /// Like `records`, but allows filling in their digests.
+    fn records_mut(&mut self) -> impl Iterator<Item = &mut TagRecord> {
+        self.compatible
+            .iter_mut()
+            .chain(self.breaking.iter_mut())
+            .chain(self.per_grade.iter_mut().map(|graded| &mut graded.record))
+    }
+
+    /// Reports the `current` tag as rebuilt if its digest differs from the `recorded_digest`.
+    fn check_rebuilt(&mut self, image: &Image, current: TagRecord, recorded_digest: &Digest) {
+        match &current.digest {
+            Some(digest) if digest != recorded_digest => self.rebuilt = Some(current),
+            Some(_) => {}
+            None => log::warn!(
+                "Failed to look up the digest of {}:{}, so it cannot be checked for rebuilds.",
+                image.name,
+                image.tag
+            ),
+        }
+    }
 }
 
 type Tag = String;
@@ -133,6 +791,7 @@ mod test {
         let image = Image {
             name: ImageName::new(None, "ubuntu".to_string()),
             tag: "14.04".to_string(),
+            digest: None,
         };
         let extractor = VersionExtractor::parse("<!>.<>").unwrap();
 
@@ -151,8 +810,10 @@ mod test {
         assert_eq!(
             actual,
             Update {
-                compatible: Some("14.05".to_string()),
-                breaking: None,
+                compatible: Some("14.05".into()),
+                searched_amount: 1,
+                compatible_grade: Some(Grade::Part(1)),
+                ..Default::default()
             },
         );
     }
@@ -162,6 +823,7 @@ mod test {
         let image = Image {
             name: ImageName::new(None, "ubuntu".to_string()),
             tag: "14.04".to_string(),
+            digest: None,
         };
         let extractor = VersionExtractor::parse("<!>.<>").unwrap();
 
@@ -180,8 +842,10 @@ mod test {
         assert_eq!(
             actual,
             Update {
-                compatible: None,
-                breaking: Some("15.02".to_string()),
+                breaking: Some("15.02".into()),
+                searched_amount: 2,
+                breaking_grade: Some(Grade::Part(0)),
+                ..Default::default()
             },
         );
     }
@@ -191,6 +855,7 @@ mod test {
         let image = Image {
             name: ImageName::new(None, "ubuntu".to_string()),
             tag: "14.04".to_string(),
+            digest: None,
         };
         let extractor = VersionExtractor::parse("<!>.<>").unwrap();
 
@@ -210,17 +875,71 @@ mod test {
         assert_eq!(
             actual,
             Update {
-                compatible: Some("14.05".to_string()),
-                breaking: Some("15.02".to_string()),
+                compatible: Some("14.05".into()),
+                breaking: Some("15.02".into()),
+                searched_amount: 2,
+                compatible_grade: Some(Grade::Part(1)),
+                breaking_grade: Some(Grade::Part(0)),
+                ..Default::default()
             },
         );
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn finds_update_without_blocking() {
+        use crate::tag_fetcher::asynchronous::test::block_on;
+
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: Some("sha256:1404".to_string()),
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+
+        let mut fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "15.02".to_string(),
+                "14.05".to_string(),
+                "14.04".to_string(),
+            ],
+        );
+        fetcher.insert_digest(image.name.clone(), "14.04", "sha256:1404");
+        fetcher.insert_digest(image.name.clone(), "14.05", "sha256:1405");
+
+        let result = block_on(find_update_async(&fetcher, &image, &extractor));
+        let actual = result.unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!(
+            actual.compatible.unwrap().digest,
+            Some("sha256:1405".into())
+        );
+        assert_eq!(actual.breaking, Some("15.02".into()));
+        assert_eq!(actual.searched_amount, 2);
+    }
+
+    /// Async runtimes like tokio require spawned futures to be `Send`.
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_search_can_be_spawned() {
+        fn assert_send(_: &impl Send) {}
+
+        let fetcher = crate::tag_fetcher::DockerHubTagFetcher::new();
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+        assert_send(&find_update_async(&fetcher, &image, &extractor));
+    }
+
     #[test]
     fn ignores_lesser_version() {
         let image = Image {
             name: ImageName::new(None, "ubuntu".to_string()),
             tag: "14.04".to_string(),
+            digest: None,
         };
         let extractor = VersionExtractor::parse("<>.<>").unwrap();
 
@@ -238,8 +957,8 @@ mod test {
         assert_eq!(
             actual,
             Update {
-                compatible: None,
-                breaking: None,
+                searched_amount: 1,
+                ..Default::default()
             },
         );
     }
@@ -249,6 +968,7 @@ mod test {
         let image = Image {
             name: ImageName::new(None, "ubuntu".to_string()),
             tag: "14.04".to_string(),
+            digest: None,
         };
         let extractor = VersionExtractor::parse("<!>.<>").unwrap();
 
@@ -268,11 +988,465 @@ mod test {
         );
     }
 
+    #[test]
+    fn finds_updates_in_order() {
+        let ubuntu = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: None,
+        };
+        let alpine = Image {
+            name: ImageName::new(None, "alpine".to_string()),
+            tag: "3.12".to_string(),
+            digest: None,
+        };
+        let missing = Image {
+            name: ImageName::new(None, "missing".to_string()),
+            tag: "1.0".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+
+        let mut fetcher = ArrayFetcher::with(
+            ubuntu.name.clone(),
+            vec!["14.05".to_string(), "14.04".to_string()],
+        );
+        fetcher.insert(
+            alpine.name.clone(),
+            vec!["4.0".to_string(), "3.12".to_string()],
+        );
+
+        let jobs = vec![
+            (ubuntu, extractor.clone(), SearchOptions::default()),
+            (missing.clone(), extractor.clone(), SearchOptions::default()),
+            (alpine, extractor, SearchOptions::default()),
+        ];
+        let finished = AtomicUsize::new(0);
+        let results = find_updates(&fetcher, &jobs, 2, |_| {
+            finished.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(finished.into_inner(), 3);
+        assert_eq!(
+            results,
+            vec![
+                Ok(Update {
+                    compatible: Some("14.05".into()),
+                    searched_amount: 1,
+                    compatible_grade: Some(Grade::Part(1)),
+                    ..Default::default()
+                }),
+                Err(FindUpdateError::FetchError(
+                    tag_fetcher::test::FetchError::new(missing.name.to_string())
+                )),
+                Ok(Update {
+                    breaking: Some("4.0".into()),
+                    searched_amount: 2,
+                    breaking_grade: Some(Grade::Part(0)),
+                    ..Default::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_tags_with_the_current_version() {
+        let image = Image {
+            name: ImageName::new(None, "python".to_string()),
+            tag: "3.9-slim".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>[.<>]-{slim|slim-bullseye}").unwrap();
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "3.9.0-slim-bullseye".to_string(),
+                "3.9-slim".to_string(),
+                "3.8-slim".to_string(),
+            ],
+        );
+
+        let update = find_update(&fetcher, &image, &extractor).unwrap();
+        assert_eq!(update.compatible, None);
+        assert_eq!(update.breaking, None);
+    }
+
+    #[test]
+    fn skips_tags_missing_required_platforms() {
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+        let record = |name: &str, platforms: &[&str]| TagRecord {
+            platforms: platforms
+                .iter()
+                .map(|platform| platform.parse().unwrap())
+                .collect(),
+            ..TagRecord::from(name)
+        };
+
+        let mut fetcher = ArrayFetcher::new();
+        fetcher.insert_records(
+            image.name.clone(),
+            vec![
+                record("15.01", &["linux/amd64"]),
+                record("14.06", &["linux/amd64"]),
+                record("14.05", &["linux/amd64", "linux/arm64/v8"]),
+                record("14.04", &["linux/amd64", "linux/arm64/v8"]),
+            ],
+        );
+        let options = SearchOptions::default().with_platforms(vec![
+            "linux/amd64".parse().unwrap(),
+            "linux/arm64".parse().unwrap(),
+        ]);
+
+        let result = find_update_with_options(&fetcher, &image, &extractor, &options);
+        assert_eq!(
+            result,
+            Ok(Update {
+                compatible: Some(record("14.05", &["linux/amd64", "linux/arm64/v8"])),
+                searched_amount: 3,
+                compatible_grade: Some(Grade::Part(1)),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn resolves_digests_of_pinned_images() {
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: Some("sha256:1404".to_string()),
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+        let mut fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "15.01".to_string(),
+                "14.05".to_string(),
+                "14.04".to_string(),
+            ],
+        );
+        fetcher.insert_digest(image.name.clone(), "15.01", "sha256:1501");
+        fetcher.insert_digest(image.name.clone(), "14.05", "sha256:1405");
+
+        let update = find_update(&fetcher, &image, &extractor).unwrap();
+        let pinned = |record: Option<TagRecord>| record.and_then(|record| record.digest);
+        assert_eq!(pinned(update.compatible), Some("sha256:1405".to_string()));
+        assert_eq!(pinned(update.breaking), Some("sha256:1501".to_string()));
+    }
+
+    #[test]
+    fn detects_rebuilt_tag() {
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+        let mut fetcher = ArrayFetcher::new();
+        fetcher.insert_records(
+            image.name.clone(),
+            vec![TagRecord {
+                digest: Some("sha256:rebuilt".to_string()),
+                ..TagRecord::from("14.04")
+            }],
+        );
+
+        let rebuilt = |recorded: &str| {
+            let options = SearchOptions::default().with_recorded_digest(Some(recorded.to_string()));
+            find_update_with_options(&fetcher, &image, &extractor, &options)
+                .unwrap()
+                .rebuilt
+        };
+        assert_eq!(
+            rebuilt("sha256:original").and_then(|record| record.digest),
+            Some("sha256:rebuilt".to_string())
+        );
+        assert_eq!(rebuilt("sha256:rebuilt"), None);
+    }
+
+    #[test]
+    fn selects_highest_versions_regardless_of_push_order() {
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "14.04".to_string(),
+                "15.01".to_string(),
+                "16.01".to_string(),
+                "14.06".to_string(),
+                "14.05".to_string(),
+                "13.01".to_string(),
+            ],
+        );
+        let options = SearchOptions::default().with_order(SearchOrder::Version);
+
+        let result = find_update_with_options(&fetcher, &image, &extractor, &options);
+        assert_eq!(
+            result,
+            Ok(Update {
+                compatible: Some("14.06".into()),
+                breaking: Some("16.01".into()),
+                searched_amount: 6,
+                compatible_grade: Some(Grade::Part(1)),
+                breaking_grade: Some(Grade::Part(0)),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn searches_unordered_tags_by_version() {
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "9.0".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<>.<>").unwrap();
+        // Tags in reverse lexical order, as listed by a registry without push dates.
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec!["9.0".to_string(), "10.0".to_string(), "1.0".to_string()],
+        )
+        .unordered();
+
+        let update = find_update(&fetcher, &image, &extractor).unwrap();
+        assert_eq!(update.compatible, Some("10.0".into()));
+        assert_eq!(update.breaking, None);
+    }
+
+    #[test]
+    fn stops_adaptive_search_at_lower_version() {
+        let image = Image {
+            name: ImageName::new(None, "ubuntu".to_string()),
+            tag: "14.04".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>").unwrap();
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "15.01".to_string(),
+                "latest".to_string(),
+                "14.03".to_string(),
+                "14.05".to_string(),
+            ],
+        );
+        let options = SearchOptions::default().with_adaptive_limit(true);
+
+        let result = find_update_with_options(&fetcher, &image, &extractor, &options);
+        assert_eq!(
+            result,
+            Ok(Update {
+                breaking: Some("15.01".into()),
+                // `latest` lacks the pattern's literal `.`, so the fetcher skips it.
+                searched_amount: 2,
+                breaking_grade: Some(Grade::Part(0)),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn skips_pre_releases_unless_requested() {
+        let image = Image {
+            name: ImageName::new(None, "python".to_string()),
+            tag: "3.9.1".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>.<>[<pre>]").unwrap();
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "4.0.0a1".to_string(),
+                "3.10.0rc2".to_string(),
+                "3.9.2".to_string(),
+                "3.9.1".to_string(),
+            ],
+        );
+
+        let result = find_update(&fetcher, &image, &extractor);
+        assert_eq!(
+            result,
+            Ok(Update {
+                compatible: Some("3.9.2".into()),
+                searched_amount: 3,
+                compatible_grade: Some(Grade::Part(2)),
+                ..Default::default()
+            })
+        );
+
+        let options = SearchOptions::default().with_pre_releases(true);
+        let result = find_update_with_options(&fetcher, &image, &extractor, &options);
+        assert_eq!(
+            result,
+            Ok(Update {
+                compatible: Some("3.10.0rc2".into()),
+                breaking: Some("4.0.0a1".into()),
+                searched_amount: 2,
+                compatible_grade: Some(Grade::Part(1)),
+                breaking_grade: Some(Grade::Part(0)),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_pinned_wildcards() {
+        let image = Image {
+            name: ImageName::new(None, "node".to_string()),
+            tag: "14.5.0-alpine3.12".to_string(),
+            digest: None,
+        };
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "14.6.0-alpine3.13".to_string(),
+                "14.5.0-alpine3.13".to_string(),
+                "14.5.1-alpine3.12".to_string(),
+                "14.5.0-alpine3.12".to_string(),
+            ],
+        );
+
+        let floating = VersionExtractor::parse("<!>.<>.<>-alpine<*>").unwrap();
+        let result = find_update(&fetcher, &image, &floating);
+        assert_eq!(
+            result,
+            Ok(Update {
+                compatible: Some("14.6.0-alpine3.13".into()),
+                searched_amount: 1,
+                compatible_grade: Some(Grade::Part(1)),
+                ..Default::default()
+            })
+        );
+
+        let pinned = VersionExtractor::parse("<!>.<>.<>-alpine<=>").unwrap();
+        let result = find_update(&fetcher, &image, &pinned);
+        assert_eq!(
+            result,
+            Ok(Update {
+                compatible: Some("14.5.1-alpine3.12".into()),
+                searched_amount: 3,
+                compatible_grade: Some(Grade::Part(2)),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn reports_updated_group() {
+        let image = Image {
+            name: ImageName::new(None, "nginx".to_string()),
+            tag: "1.21.6-alpine3.16".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>.<>-alpine(alpine:<!>.<>)").unwrap();
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "1.21.6-alpine4.0".to_string(),
+                "1.21.6-alpine3.17".to_string(),
+                "1.21.6-alpine3.16".to_string(),
+            ],
+        );
+
+        let result = find_update(&fetcher, &image, &extractor);
+        assert_eq!(
+            result,
+            Ok(Update {
+                compatible: Some("1.21.6-alpine3.17".into()),
+                breaking: Some("1.21.6-alpine4.0".into()),
+                searched_amount: 2,
+                compatible_group: Some("alpine".to_string()),
+                breaking_group: Some("alpine".to_string()),
+                compatible_grade: Some(Grade::Part(1)),
+                breaking_grade: Some(Grade::Part(0)),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn skips_downgraded_groups() {
+        let image = Image {
+            name: ImageName::new(None, "nginx".to_string()),
+            tag: "1.21.6-alpine3.16".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>.<>-alpine(alpine:<!>.<>)").unwrap();
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            vec![
+                "1.21.7-alpine2.0".to_string(),
+                "1.21.7-alpine3.16".to_string(),
+                "1.21.6-alpine3.16".to_string(),
+            ],
+        );
+
+        let update = find_update(&fetcher, &image, &extractor).unwrap();
+        assert_eq!(update.compatible, Some("1.21.7-alpine3.16".into()));
+        assert_eq!(update.breaking, None);
+    }
+
+    #[test]
+    fn finds_latest_update_per_grade() {
+        let image = Image {
+            name: ImageName::new(None, "node".to_string()),
+            tag: "14.4.0".to_string(),
+            digest: None,
+        };
+        let extractor = VersionExtractor::parse("<!>.<>.<>").unwrap();
+        let tags = vec![
+            "16.1.0", "15.0.0", "14.6.0", "14.5.1", "14.4.2", "14.4.1", "14.4.0",
+        ];
+        let fetcher = ArrayFetcher::with(
+            image.name.clone(),
+            tags.into_iter().map(str::to_string).collect(),
+        );
+        let graded = |name: &str, update_type, grade| GradedUpdate {
+            record: name.into(),
+            update_type,
+            group: None,
+            grade,
+        };
+        let expected = Update {
+            compatible: Some("14.6.0".into()),
+            breaking: Some("16.1.0".into()),
+            searched_amount: 7,
+            compatible_grade: Some(Grade::Part(1)),
+            breaking_grade: Some(Grade::Part(0)),
+            per_grade: vec![
+                graded("14.4.2", UpdateType::Compatible, Grade::Part(2)),
+                graded("14.6.0", UpdateType::Compatible, Grade::Part(1)),
+                graded("16.1.0", UpdateType::Breaking, Grade::Part(0)),
+            ],
+            ..Default::default()
+        };
+
+        for order in &[SearchOrder::Chronological, SearchOrder::Version] {
+            let options = SearchOptions::default()
+                .with_updates_per_grade(true)
+                .with_order(*order);
+            let result = find_update_with_options(&fetcher, &image, &extractor, &options);
+            assert_eq!(result, Ok(expected.clone()));
+        }
+    }
+
     #[test]
     fn forwards_fetch_failure() {
         let image = Image {
             name: ImageName::new(None, "ubuntu".to_string()),
             tag: "14.04".to_string(),
+            digest: None,
         };
         let extractor = VersionExtractor::parse("<!>.<>").unwrap();
 
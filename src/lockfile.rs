@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::image::{Digest, Image};
+use crate::json_file;
+
+/// Records the digest each image's tag pointed to when it was locked.
+///
+/// Comparing the recorded digests with the registry's detects tags that
+/// were pushed again, e.g. to ship security fixes under the same tag.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    images: BTreeMap<String, Digest>,
+}
+
+impl Lockfile {
+    /// Loads the lockfile at `path`, treating a missing file as an empty lockfile.
+    pub fn load_or_default(path: &Path) -> Result<Self, LockfileError> {
+        json_file::load_or_default(path, "lockfile")
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, LockfileError> {
+        json_file::from_path(path, "lockfile")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), LockfileError> {
+        json_file::save(self, path, "lockfile")
+    }
+
+    /// The digest recorded for the `image`'s name and tag, ignoring the digest the image is pinned to.
+    pub fn digest(&self, image: &Image) -> Option<&Digest> {
+        self.images.get(&Self::key(image))
+    }
+
+    /// Replaces the digest recorded for the `image`'s name and tag.
+    pub fn insert(&mut self, image: &Image, digest: Digest) {
+        self.images.insert(Self::key(image), digest);
+    }
+
+    fn key(image: &Image) -> String {
+        format!("{}:{}", image.name, image.tag)
+    }
+}
+
+pub type LockfileError = json_file::Error;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ignores_pinned_digest() {
+        let mut lockfile = Lockfile::default();
+        lockfile.insert(&"ubuntu:20.04".parse().unwrap(), "sha256:2004".to_string());
+
+        let pinned = format!("ubuntu:20.04@sha256:{}", "a".repeat(64));
+        assert_eq!(
+            lockfile.digest(&pinned.parse().unwrap()),
+            Some(&"sha256:2004".to_string())
+        );
+        assert_eq!(lockfile.digest(&"ubuntu:18.04".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn round_trips_through_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("uptag.lock");
+
+        let mut lockfile = Lockfile::load_or_default(&path).unwrap();
+        lockfile.insert(&"ubuntu:20.04".parse().unwrap(), "sha256:2004".to_string());
+        lockfile.save(&path).unwrap();
+
+        assert_eq!(Lockfile::from_path(&path).unwrap(), lockfile);
+    }
+}
@@ -1,5 +1,6 @@
 use std::fs;
-use std::path::{self, PathBuf};
+use std::path::{self, Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use indicatif::{ProgressBar, ProgressStyle};
@@ -9,16 +10,23 @@ use structopt::StructOpt;
 use thiserror::Error;
 
 use docker_compose::BuildContext;
+use uptag::annotation::Annotation;
+use uptag::credentials::DockerConfig;
 use uptag::docker_compose;
 use uptag::dockerfile;
 use uptag::dockerfile::CheckError;
-use uptag::image::ImageName;
+use uptag::image::{Image, ImageName, Platform};
+use uptag::lockfile::Lockfile;
 use uptag::report::{
     docker_compose::DockerComposeReport, dockerfile::DockerfileReport, UpdateLevel,
 };
-use uptag::tag_fetcher::{DockerHubTagFetcher, TagFetcher};
+use uptag::tag_fetcher::cache::{CacheMode, CachingTagFetcher, TagCache};
+use uptag::tag_fetcher::http::{self, Cassette, HttpClient, HttpConfig};
+use uptag::tag_fetcher::memo::MemoizingTagFetcher;
+use uptag::tag_fetcher::snapshot::{Snapshot, SnapshotTagFetcher};
+use uptag::tag_fetcher::{RegistryTagFetcher, TagFetcher, TagRecord};
 use uptag::version::extractor::VersionExtractor;
-use uptag::FindUpdateError;
+use uptag::{display_error, FindUpdateError, SearchOptions, SearchOrder, Update};
 
 /// Check Docker image tags for updates.
 #[derive(Debug, StructOpt)]
@@ -31,17 +39,35 @@ Use `<>` to match a number. Everything else will be matched literally.
 Specify which numbers indicate breaking changes using `<!>`. Uptag will report breaking changes separately from compatible changes.
 - Given pattern `<!>.<>.<>` and the current tag `1.4.12`:
   - compatible updates: `1.6.12` and `1.4.13`
-  - breaking updates: `2.4.12` and `3.5.13`")]
+  - breaking updates: `2.4.12` and `3.5.13`
+
+Wrap parts that a tag may omit in brackets. Omitted numbers compare as `0`.
+- `<!>.<>[.<>]` will match `3.9` and `3.9.1`, with `3.9` being equal to `3.9.0`.
+
+List alternative spellings of a literal in braces, separated by `|`.
+- `<>.<>-{slim|slim-bullseye}` will match `3.9-slim` and `3.9-slim-bullseye`.
+
+Use `<pre>` to match a pre-release qualifier (`alpha`, `beta`, `rc`, or the short forms `a` and `b`) followed by a number. Pre-releases order below the release and are only proposed with `--pre-releases`.
+- `<!>.<>.<>[-<pre>]` will match `1.5.0`, `1.5.0-beta.3` and `1.5.0-alpha1`.
+
+Use `<*>` to match a segment without `-` that is ignored when comparing versions, or `<=>` to also require it to keep the current tag's value.
+- `<!>.<>.<>-alpine<*>` will match `14.5.0-alpine3.12` and `14.5.0-alpine3.13`.
+
+Put the numbers of another version in the same tag into a named group `(<name>:...)`, which has its own breaking numbers.
+- Given pattern `<!>.<>.<>-alpine(alpine:<!>.<>)` and the current tag `1.21.6-alpine3.16`, `1.21.6-alpine3.17` is a compatible `alpine` update.")]
+#[allow(clippy::large_enum_variant)]
 enum Opts {
     Fetch(FetchOpts),
     Check(CheckOpts),
     CheckCompose(CheckComposeOpts),
 }
 
-/// Lists the latest tags for an image from DockerHub.
+/// Lists the latest tags for an image from its registry.
 #[derive(Debug, StructOpt)]
 struct FetchOpts {
     /// The image name for which tags should be fetched.
+    ///
+    /// Images on registries other than DockerHub are prefixed with the registry's host, e.g. `ghcr.io/org/app`.
     image: ImageName,
     /// A pattern to filter the tags with. Only matching tags will be output.
     #[structopt(short, long)]
@@ -56,6 +82,15 @@ struct FetchOpts {
     /// Example: `uptag fetch --amount 50 --search-limit 500 --pattern '<!>.<>' ubuntu` will stop after 50 matching tags or after looking through the latest 500 tags, whichever happens first.
     #[structopt(short, long, default_value = "100")]
     search_limit: usize,
+    #[structopt(flatten)]
+    registry: RegistryOpts,
+    #[structopt(flatten)]
+    cache: CacheOpts,
+    /// Stores the fetched tags in a snapshot file for use with `--offline`.
+    ///
+    /// The tags are added to the file if it already exists, replacing earlier tags of the same image.
+    #[structopt(long, parse(from_os_str))]
+    save: Option<PathBuf>,
 }
 
 /// Reports on update status for all images in a Dockerfile.
@@ -76,9 +111,27 @@ struct CheckOpts {
     /// The Dockerfile to check.
     #[structopt(parse(from_os_str))]
     file: PathBuf,
-    /// Limits how many tags will be fetched from DockerHub before stopping the search.
-    #[structopt(short, long, default_value = "100")]
-    search_limit: usize,
+    #[structopt(flatten)]
+    search: SearchOpts,
+    #[structopt(flatten)]
+    registry: RegistryOpts,
+    #[structopt(flatten)]
+    cache: CacheOpts,
+    /// Reads the tags from a snapshot file created by `uptag fetch --save` instead of the registries.
+    #[structopt(long, parse(from_os_str), value_name = "snapshot")]
+    offline: Option<PathBuf>,
+    /// Reports images whose tag was pushed again since its digest was recorded in this lockfile.
+    #[structopt(long, parse(from_os_str), value_name = "lockfile")]
+    lockfile: Option<PathBuf>,
+    /// Records the current digest of each checked image in the lockfile.
+    #[structopt(long, requires = "lockfile")]
+    update_lockfile: bool,
+    /// Exits with code 3 instead of 0 if images were rebuilt, but none has updates.
+    #[structopt(long)]
+    signal_rebuilt: bool,
+    /// How many images to check at the same time.
+    #[structopt(short = "j", long, default_value = "4")]
+    concurrency: usize,
 }
 
 /// Reports on update status for all services in a docker-compose file.
@@ -86,7 +139,7 @@ struct CheckOpts {
 #[structopt(after_help = r#"SPECIFYING PATTERNS:
 Each service must associate a pattern with its images. There are two supported declarations.
 
-A service can specify an `image` field, pointing to an image on DockerHub. Such an image needs to be annotated with a pattern and declare a specific tag that matches that pattern. The pattern must be given as a comment in the line before the `image` field in the following format:
+A service can specify an `image` field, pointing to an image on DockerHub or another registry. Such an image needs to be annotated with a pattern and declare a specific tag that matches that pattern. The pattern must be given as a comment in the line before the `image` field in the following format:
 # uptag --pattern "<pattern>"
 
 Alternatively, a service can point to a folder containing a Dockerfile via its `build` field. That Dockerfile needs to specify patterns as documented in `uptag check --help`.
@@ -107,9 +160,268 @@ struct CheckComposeOpts {
     /// The docker-compose file to check.
     #[structopt(parse(from_os_str))]
     file: PathBuf,
-    /// Limits how many tags will be fetched from DockerHub before stopping the search.
+    #[structopt(flatten)]
+    search: SearchOpts,
+    #[structopt(flatten)]
+    registry: RegistryOpts,
+    #[structopt(flatten)]
+    cache: CacheOpts,
+    /// Reads the tags from a snapshot file created by `uptag fetch --save` instead of the registries.
+    #[structopt(long, parse(from_os_str), value_name = "snapshot")]
+    offline: Option<PathBuf>,
+    /// Reports images whose tag was pushed again since its digest was recorded in this lockfile.
+    #[structopt(long, parse(from_os_str), value_name = "lockfile")]
+    lockfile: Option<PathBuf>,
+    /// Records the current digest of each checked image in the lockfile.
+    #[structopt(long, requires = "lockfile")]
+    update_lockfile: bool,
+    /// Exits with code 3 instead of 0 if images were rebuilt, but none has updates.
+    #[structopt(long)]
+    signal_rebuilt: bool,
+    /// How many images to check at the same time.
+    #[structopt(short = "j", long, default_value = "4")]
+    concurrency: usize,
+}
+
+#[derive(Debug, StructOpt)]
+struct SearchOpts {
+    /// Limits how many tags will be fetched from the registry before stopping the search.
     #[structopt(short, long, default_value = "100")]
     search_limit: usize,
+    /// Keeps searching past the search limit until the current tag or a lower version is found.
+    ///
+    /// Searches at most `--max-search-limit` tags.
+    #[structopt(long)]
+    adaptive: bool,
+    /// Limits how many tags will be fetched with `--adaptive`.
+    #[structopt(long, default_value = "1000")]
+    max_search_limit: usize,
+    /// Stops searching the tags of an image after this long, e.g. `30s` or `2m`.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    time_budget: Option<Duration>,
+    /// Only proposes updates that are published for this platform, e.g. `linux/arm64`.
+    ///
+    /// Applies to all images in addition to the platforms they declare. Can be given multiple times.
+    #[structopt(
+        long = "platform",
+        value_name = "platform",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    platforms: Vec<Platform>,
+    /// How updates are selected among the fetched tags.
+    ///
+    /// `chronological` assumes that the registry lists the newest tags first and stops at the current tag.
+    /// `version` searches all fetched tags for the highest versions, which finds updates even if older tags were pushed again.
+    #[structopt(
+        long,
+        default_value = "chronological",
+        possible_values = &["chronological", "version"]
+    )]
+    order: SearchOrder,
+    /// Proposes pre-releases as updates, for patterns with a `<pre>` placeholder.
+    #[structopt(long)]
+    pre_releases: bool,
+    /// Proposes the latest update of each grade, e.g. the latest patch, minor and major update.
+    #[structopt(long)]
+    per_grade: bool,
+}
+
+impl SearchOpts {
+    /// How many tags the fetcher is limited to.
+    fn fetch_limit(&self) -> usize {
+        if self.adaptive {
+            std::cmp::max(self.search_limit, self.max_search_limit)
+        } else {
+            self.search_limit
+        }
+    }
+
+    /// The options that apply to all images.
+    fn search_options(&self) -> SearchOptions {
+        SearchOptions::default()
+            .with_platforms(self.platforms.clone())
+            .with_order(self.order)
+            .with_adaptive_limit(self.adaptive)
+            .with_time_budget(self.time_budget)
+            .with_pre_releases(self.pre_releases)
+            .with_updates_per_grade(self.per_grade)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RegistryOpts {
+    /// Fetches images on DockerHub from a server implementing DockerHub's API instead, e.g. a mirror.
+    ///
+    /// If given multiple times, the URLs are tried in turn until one succeeds.
+    #[structopt(
+        long = "docker-hub-url",
+        value_name = "url",
+        env = "UPTAG_DOCKER_HUB_URLS",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    docker_hub_urls: Vec<String>,
+    /// How many tags are requested from DockerHub at once, at most 100.
+    #[structopt(long, default_value = "100", parse(try_from_str = parse_page_size))]
+    page_size: usize,
+    /// Fetches the tags of images on a registry by running a plugin, e.g. `registry.example.com=/usr/local/bin/uptag-nexus`.
+    ///
+    /// The plugin receives the image as JSON on stdin and writes the tags as JSON lines to stdout, see the README.
+    /// Can be given multiple times.
+    #[structopt(
+        long = "plugin",
+        value_name = "registry=program",
+        env = "UPTAG_PLUGINS",
+        use_delimiter = true,
+        number_of_values = 1,
+        parse(try_from_str = parse_plugin)
+    )]
+    plugins: Vec<(String, PathBuf)>,
+    #[structopt(flatten)]
+    http: HttpOpts,
+}
+
+#[derive(Debug, StructOpt)]
+struct HttpOpts {
+    /// How long connecting to a registry may take, e.g. `10s`.
+    #[structopt(long, default_value = "10s", parse(try_from_str = parse_duration))]
+    connect_timeout: Duration,
+    /// How long a request to a registry may take, including reading the response.
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+    timeout: Duration,
+    /// Sends all requests through this proxy, e.g. `http://proxy.example.com:3128`.
+    ///
+    /// Without it, the proxies given in `HTTP_PROXY` and `HTTPS_PROXY` are used.
+    #[structopt(long, value_name = "url", env = "UPTAG_PROXY")]
+    proxy: Option<String>,
+    /// Hosts that are accessed without the proxy, including their subdomains.
+    #[structopt(
+        long,
+        value_name = "host",
+        env = "NO_PROXY",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    no_proxy: Vec<String>,
+    /// Trusts the certificates in this PEM file in addition to the system's, e.g. for a TLS-intercepting proxy.
+    ///
+    /// Can be given multiple times.
+    #[structopt(
+        long = "ca-cert",
+        value_name = "file",
+        parse(from_os_str),
+        number_of_values = 1
+    )]
+    ca_certificates: Vec<PathBuf>,
+    /// Identifies uptag to the registries.
+    #[structopt(long, default_value = http::DEFAULT_USER_AGENT)]
+    user_agent: String,
+    /// Records the registries' responses in this cassette file, bypassing the cache.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "cassette",
+        conflicts_with = "replay"
+    )]
+    record: Option<PathBuf>,
+    /// Answers requests with the responses recorded via `--record` instead of accessing the registries.
+    ///
+    /// Fails on requests that were not recorded.
+    #[structopt(long, parse(from_os_str), value_name = "cassette")]
+    replay: Option<PathBuf>,
+}
+
+impl HttpOpts {
+    fn http_config(&self) -> HttpConfig {
+        let mut config = HttpConfig::default()
+            .with_connect_timeout(Some(self.connect_timeout))
+            .with_timeout(Some(self.timeout))
+            .with_user_agent(self.user_agent.as_str());
+        if let Some(proxy) = &self.proxy {
+            config = config
+                .with_proxy(proxy.as_str())
+                .with_no_proxy(self.no_proxy.iter().cloned());
+        }
+        for path in &self.ca_certificates {
+            config = config.with_ca_certificate(path);
+        }
+        config
+    }
+
+    fn client(&self) -> Result<HttpClient> {
+        if let Some(path) = &self.replay {
+            return Ok(HttpClient::replaying(Cassette::from_path(path)?));
+        }
+        let client = self
+            .http_config()
+            .build_client()
+            .context("Failed to configure HTTP")?;
+        Ok(match &self.record {
+            Some(path) => HttpClient::recording(client, path),
+            None => client.into(),
+        })
+    }
+
+    /// Whether responses are recorded or replayed, which the cache would interfere with.
+    fn uses_cassette(&self) -> bool {
+        self.record.is_some() || self.replay.is_some()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct CacheOpts {
+    /// Neither reads tags from the cache nor stores fetched tags in it.
+    #[structopt(long, conflicts_with = "refresh")]
+    no_cache: bool,
+    /// Ignores cached tags, but stores fetched tags in the cache.
+    #[structopt(long)]
+    refresh: bool,
+    /// How long cached tags are used, e.g. `90s`, `30m`, `12h` or `2d`.
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_duration))]
+    cache_ttl: Duration,
+}
+
+fn parse_plugin(input: &str) -> Result<(String, PathBuf), String> {
+    match input.split_once('=') {
+        Some((registry, program)) if !registry.is_empty() && !program.is_empty() => {
+            Ok((registry.to_string(), PathBuf::from(program)))
+        }
+        _ => Err(format!("`{}` is not of the form `registry=program`", input)),
+    }
+}
+
+fn parse_page_size(input: &str) -> Result<usize, String> {
+    match input.parse() {
+        Ok(page_size) if (1..=100).contains(&page_size) => Ok(page_size),
+        _ => Err(format!("`{}` is not a number from 1 to 100", input)),
+    }
+}
+
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("`{}` does not start with a number", input))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "`{}` is not one of the units `s`, `m`, `h` or `d`",
+                unit
+            ))
+        }
+    };
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("`{}` is too long", input))
 }
 
 fn main() {
@@ -139,16 +451,20 @@ const EXIT_OK: ExitCode = ExitCode(0);
 const EXIT_NO_UPDATE: ExitCode = ExitCode(0);
 const EXIT_COMPATIBLE_UPDATE: ExitCode = ExitCode(1);
 const EXIT_BREAKING_UPDATE: ExitCode = ExitCode(2);
+const EXIT_REBUILT_UPDATE: ExitCode = ExitCode(3);
 const EXIT_ERROR: ExitCode = ExitCode(10);
 
 impl ExitCode {
-    fn from(level: UpdateLevel) -> ExitCode {
+    /// Rebuilt images only change the exit code if `signal_rebuilt` is set,
+    /// since code 3 would otherwise rank them above breaking updates.
+    fn from(level: UpdateLevel, signal_rebuilt: bool) -> ExitCode {
         use UpdateLevel::*;
         match level {
             Failure => EXIT_ERROR,
             BreakingUpdate => EXIT_BREAKING_UPDATE,
             CompatibleUpdate => EXIT_COMPATIBLE_UPDATE,
-            NoUpdates => EXIT_NO_UPDATE,
+            RebuiltUpdate if signal_rebuilt => EXIT_REBUILT_UPDATE,
+            RebuiltUpdate | NoUpdates => EXIT_NO_UPDATE,
         }
     }
 
@@ -157,10 +473,68 @@ impl ExitCode {
     }
 }
 
+/// Constructs a fetcher that authenticates with the credentials from `docker login`,
+/// caches the fetched tags and fetches each image only once.
+fn registry_fetcher(
+    search_limit: usize,
+    registry_opts: &RegistryOpts,
+    cache_opts: &CacheOpts,
+) -> Result<MemoizingTagFetcher<CachingTagFetcher<RegistryTagFetcher>>> {
+    let docker_config = DockerConfig::load().unwrap_or_else(|error| {
+        log::warn!(
+            "{}. Accessing registries anonymously.",
+            display_error(&error)
+        );
+        DockerConfig::default()
+    });
+    let client = registry_opts.http.client()?;
+    let mut fetcher = RegistryTagFetcher::with_search_limit(search_limit)
+        .with_client(client)
+        .with_docker_config(docker_config)
+        .with_docker_hub_page_size(registry_opts.page_size);
+    if !registry_opts.docker_hub_urls.is_empty() {
+        fetcher = fetcher.with_docker_hub_mirrors(registry_opts.docker_hub_urls.clone());
+    }
+    for (registry, program) in &registry_opts.plugins {
+        fetcher = fetcher.with_plugin(registry.as_str(), program);
+    }
+
+    let mode = if cache_opts.no_cache || registry_opts.http.uses_cassette() {
+        CacheMode::Bypass
+    } else if cache_opts.refresh {
+        CacheMode::Refresh
+    } else {
+        CacheMode::Use
+    };
+    let (directory, mode) = match TagCache::default_directory() {
+        Some(directory) => (directory, mode),
+        None => {
+            log::warn!("Failed to determine the cache directory, so tags will not be cached.");
+            (PathBuf::new(), CacheMode::Bypass)
+        }
+    };
+    let cache = TagCache::new(directory, cache_opts.cache_ttl);
+    let fetcher = CachingTagFetcher::new(fetcher, cache, search_limit).with_mode(mode);
+    Ok(MemoizingTagFetcher::new(fetcher))
+}
+
 fn fetch(opts: FetchOpts) -> Result<ExitCode> {
     let adjusted_search_limit = std::cmp::max(opts.search_limit, opts.amount);
-    let fetcher = DockerHubTagFetcher::with_search_limit(adjusted_search_limit);
-    let tags = fetcher.fetch(&opts.image);
+    let fetcher = registry_fetcher(adjusted_search_limit, &opts.registry, &opts.cache)?;
+    let records: Box<dyn Iterator<Item = Result<TagRecord, _>>> = match &opts.save {
+        Some(path) => {
+            let fetched = fetcher
+                .fetch(&opts.image)
+                .collect::<Result<Vec<_>, _>>()
+                .context("Failed to fetch tags")?;
+            let mut snapshot = Snapshot::load_or_default(path)?;
+            snapshot.insert(&opts.image, fetched.clone());
+            snapshot.save(path)?;
+            Box::new(fetched.into_iter().map(Ok))
+        }
+        None => Box::new(fetcher.fetch(&opts.image)),
+    };
+    let tags = records.map(|result| result.map(|record| record.name));
 
     let result = if let Some(extractor) = opts.pattern {
         let mut tag_count = 0;
@@ -202,6 +576,23 @@ fn fetch(opts: FetchOpts) -> Result<ExitCode> {
 }
 
 fn check(opts: CheckOpts) -> Result<ExitCode> {
+    match &opts.offline {
+        Some(snapshot) => {
+            let fetcher = SnapshotTagFetcher::from_path(snapshot)?;
+            check_with(&fetcher, opts)
+        }
+        None => {
+            let fetcher = registry_fetcher(opts.search.fetch_limit(), &opts.registry, &opts.cache)?;
+            check_with(&fetcher, opts)
+        }
+    }
+}
+
+fn check_with<T>(fetcher: &T, opts: CheckOpts) -> Result<ExitCode>
+where
+    T: TagFetcher + Sync,
+    T::FetchError: 'static + Send,
+{
     let file_path = opts
         .file
         .canonicalize()
@@ -213,21 +604,31 @@ fn check(opts: CheckOpts) -> Result<ExitCode> {
         )
     })?;
 
-    let fetcher = DockerHubTagFetcher::with_search_limit(opts.search_limit);
-    let images = dockerfile::parse(&input);
-    let updates = images.map(|(image, pattern_result)| {
-        let results = pattern_result
+    let mut lockfile = load_lockfile(&opts.lockfile)?;
+    let options = opts.search.search_options();
+    let mut jobs = Vec::new();
+    let images = dockerfile::parse(&input)
+        .map(|(image, annotation_result)| {
+            let job = annotation_result
+                .map(|annotation| add_job(&mut jobs, &image, annotation, &options, &lockfile));
+            (image, job)
+        })
+        .collect::<Vec<_>>();
+
+    let mut results = run_jobs(fetcher, &jobs, opts.concurrency, |_| {});
+    let search_depth = display_search_depth(&jobs, &results, opts.search.search_limit);
+    if let (Some(path), true) = (&opts.lockfile, opts.update_lockfile) {
+        update_lockfile(fetcher, &jobs, &mut lockfile, path)?;
+    }
+    let updates = images.into_iter().map(|(image, job)| {
+        let result = job
             .map_err(UpdateError::Check)
-            .and_then(|pattern| {
-                let extractor = VersionExtractor::new(pattern);
-
-                uptag::find_update(&fetcher, &image, &extractor).map_err(UpdateError::FindUpdate)
-            });
-        (image, results)
+            .and_then(|index| take_result(&mut results, index));
+        (image, result)
     });
 
     let dockerfile_report = DockerfileReport::from(updates);
-    let exit_code = ExitCode::from(dockerfile_report.report.update_level());
+    let exit_code = ExitCode::from(dockerfile_report.report.update_level(), opts.signal_rebuilt);
 
     println!(
         "Report for Dockerfile at `{}`:\n",
@@ -238,6 +639,9 @@ fn check(opts: CheckOpts) -> Result<ExitCode> {
         println!();
     }
     println!("{}", dockerfile_report.display_successes());
+    if let Some(search_depth) = search_depth {
+        println!("\n{}", search_depth);
+    }
 
     Ok(exit_code)
 }
@@ -260,6 +664,23 @@ where
 }
 
 fn check_compose(opts: CheckComposeOpts) -> Result<ExitCode> {
+    match &opts.offline {
+        Some(snapshot) => {
+            let fetcher = SnapshotTagFetcher::from_path(snapshot)?;
+            check_compose_with(&fetcher, opts)
+        }
+        None => {
+            let fetcher = registry_fetcher(opts.search.fetch_limit(), &opts.registry, &opts.cache)?;
+            check_compose_with(&fetcher, opts)
+        }
+    }
+}
+
+fn check_compose_with<T>(fetcher: &T, opts: CheckComposeOpts) -> Result<ExitCode>
+where
+    T: TagFetcher + Sync,
+    T::FetchError: 'static + Send,
+{
     let compose_file_path = opts
         .file
         .canonicalize()
@@ -274,24 +695,16 @@ fn check_compose(opts: CheckComposeOpts) -> Result<ExitCode> {
         docker_compose::parse(&compose_file).context("Failed to parse docker-compose file")?;
 
     let compose_dir = opts.file.parent().unwrap();
-    let fetcher = DockerHubTagFetcher::with_search_limit(opts.search_limit);
 
-    let progress_bar = ProgressBar::new(services.len() as u64)
-        .with_style(ProgressStyle::default_bar().template("{msg}\n{wide_bar} {pos}/{len}"));
-
-    let updates = services.into_iter().map(|(service_name, build_context)| {
-        progress_bar.set_message(&format!(
-            "Fetching for service `{service}`",
-            service = service_name
-        ));
-        progress_bar.inc(1);
-
-        match build_context {
-            docker_compose::BuildContext::Image(image, pattern) => {
-                let extractor = VersionExtractor::new(pattern);
-                let update = uptag::find_update(&fetcher, &image, &extractor)
-                    .map_err(UpdateError::FindUpdate);
-                (service_name, BuildContext::Image(image, update))
+    let mut lockfile = load_lockfile(&opts.lockfile)?;
+    let options = opts.search.search_options();
+    let mut jobs = Vec::new();
+    let planned = services
+        .into_iter()
+        .map(|(service_name, build_context)| match build_context {
+            docker_compose::BuildContext::Image(image, annotation) => {
+                let job = add_job(&mut jobs, &image, annotation, &options, &lockfile);
+                (service_name, BuildContext::Image(image, job))
             }
             docker_compose::BuildContext::Folder(relative_path, ()) => {
                 let path = compose_dir.join(relative_path).join("Dockerfile");
@@ -300,41 +713,81 @@ fn check_compose(opts: CheckComposeOpts) -> Result<ExitCode> {
                     .map(|path| display_canonicalized(&path))
                     .unwrap_or_else(|_| clean_path(&path));
 
-                let updates_result = fs::read_to_string(&path)
+                let images_result = fs::read_to_string(&path)
                     .map_err(|error| UpdateError::IO {
                         file: clean_path(&path),
                         source: error,
                     })
                     .map(|input| {
-                        let images = dockerfile::parse(&input);
-                        let updates = images.map(|(image, pattern_result)| {
-                            let results =
-                                pattern_result
-                                    .map_err(UpdateError::Check)
-                                    .and_then(|pattern| {
-                                        let extractor = VersionExtractor::new(pattern);
-
-                                        uptag::find_update(&fetcher, &image, &extractor)
-                                            .map_err(UpdateError::FindUpdate)
-                                    });
-                            (image, results)
-                        });
-                        updates.collect::<Vec<_>>()
+                        dockerfile::parse(&input)
+                            .map(|(image, annotation_result)| {
+                                let job = annotation_result.map(|annotation| {
+                                    add_job(&mut jobs, &image, annotation, &options, &lockfile)
+                                });
+                                (image, job)
+                            })
+                            .collect::<Vec<_>>()
                     });
 
                 (
                     service_name,
-                    BuildContext::Folder(path_display, updates_result),
+                    BuildContext::Folder(path_display, images_result),
                 )
             }
-        }
+        })
+        .collect::<Vec<_>>();
+
+    let progress_bar = ProgressBar::new(jobs.len() as u64)
+        .with_style(ProgressStyle::default_bar().template("{msg}\n{wide_bar} {pos}/{len}"));
+    progress_bar.set_message("Fetching tags");
+
+    let mut results = run_jobs(fetcher, &jobs, opts.concurrency, |index| {
+        progress_bar.set_message(&format!(
+            "Fetched tags for `{image}`",
+            image = jobs[index].0.name
+        ));
+        progress_bar.inc(1);
     });
+    let search_depth = display_search_depth(&jobs, &results, opts.search.search_limit);
+    if let (Some(path), true) = (&opts.lockfile, opts.update_lockfile) {
+        update_lockfile(fetcher, &jobs, &mut lockfile, path)?;
+    }
+
+    let updates = planned
+        .into_iter()
+        .map(|(service_name, build_context)| match build_context {
+            BuildContext::Image(image, job) => {
+                let update = take_result(&mut results, job);
+                (service_name, BuildContext::Image(image, update))
+            }
+            BuildContext::Folder(path_display, images_result) => {
+                let updates_result = images_result.map(|images| {
+                    images
+                        .into_iter()
+                        .map(|(image, job)| {
+                            let result = job
+                                .map_err(UpdateError::Check)
+                                .and_then(|index| take_result(&mut results, index));
+                            (image, result)
+                        })
+                        .collect::<Vec<_>>()
+                });
+                (
+                    service_name,
+                    BuildContext::Folder(path_display, updates_result),
+                )
+            }
+        })
+        .collect::<Vec<_>>();
 
-    let docker_compose_report = DockerComposeReport::from(updates);
+    let docker_compose_report = DockerComposeReport::from(updates.into_iter());
 
     progress_bar.finish_and_clear();
 
-    let exit_code = ExitCode::from(docker_compose_report.report.update_level());
+    let exit_code = ExitCode::from(
+        docker_compose_report.report.update_level(),
+        opts.signal_rebuilt,
+    );
 
     println!(
         "Report for docker-compose file at `{}`:\n",
@@ -345,10 +798,122 @@ fn check_compose(opts: CheckComposeOpts) -> Result<ExitCode> {
         println!("\n");
     }
     println!("{}", docker_compose_report.display_successes());
+    if let Some(search_depth) = search_depth {
+        println!("\n\n{}", search_depth);
+    }
 
     Ok(exit_code)
 }
 
+type Job = (Image, VersionExtractor, SearchOptions);
+
+/// Schedules checking the `image` and returns the index of the job.
+///
+/// The platforms in `options` are required in addition to those in the `annotation`.
+fn add_job(
+    jobs: &mut Vec<Job>,
+    image: &Image,
+    annotation: Annotation,
+    options: &SearchOptions,
+    lockfile: &Lockfile,
+) -> usize {
+    let annotation = annotation.with_platforms(options.platforms().iter().cloned());
+    let options = options
+        .clone()
+        .with_platforms(annotation.platforms)
+        .with_recorded_digest(lockfile.digest(image).cloned());
+    jobs.push((
+        image.clone(),
+        VersionExtractor::new(annotation.pattern),
+        options,
+    ));
+    jobs.len() - 1
+}
+
+fn load_lockfile(path: &Option<PathBuf>) -> Result<Lockfile> {
+    Ok(match path {
+        Some(path) => Lockfile::load_or_default(path)?,
+        None => Lockfile::default(),
+    })
+}
+
+/// Records the current digest of each job's image in the `lockfile` and saves it to `path`.
+///
+/// Images whose digest cannot be looked up keep their previous entry.
+fn update_lockfile<T>(fetcher: &T, jobs: &[Job], lockfile: &mut Lockfile, path: &Path) -> Result<()>
+where
+    T: TagFetcher,
+{
+    for (image, _, _) in jobs {
+        match fetcher.digest(&image.name, &image.tag) {
+            Ok(Some(digest)) => lockfile.insert(image, digest),
+            Ok(None) => log::warn!("The registry did not report a digest for {}.", image),
+            Err(error) => log::warn!("Failed to look up the digest of {}: {}", image, error),
+        }
+    }
+    lockfile.save(path)?;
+    Ok(())
+}
+
+type JobResult<E> = Result<Update, UpdateError<E>>;
+
+/// Checks all `jobs` concurrently.
+///
+/// Each result has to be taken out with `take_result`.
+fn run_jobs<T>(
+    fetcher: &T,
+    jobs: &[Job],
+    concurrency: usize,
+    on_finished: impl Fn(usize) + Sync,
+) -> Vec<Option<JobResult<T::FetchError>>>
+where
+    T: TagFetcher + Sync,
+    T::FetchError: Send,
+{
+    uptag::find_updates(fetcher, jobs, concurrency, on_finished)
+        .into_iter()
+        .map(|result| Some(result.map_err(UpdateError::FindUpdate)))
+        .collect()
+}
+
+/// Lists the images for which more tags than the `search_limit` were searched, e.g. with `--adaptive`.
+fn display_search_depth<E>(
+    jobs: &[Job],
+    results: &[Option<JobResult<E>>],
+    search_limit: usize,
+) -> Option<String>
+where
+    E: 'static + std::error::Error,
+{
+    let deep_searches = jobs
+        .iter()
+        .zip(results)
+        .filter_map(|((image, _, _), result)| match result {
+            Some(Ok(update)) if update.searched_amount > search_limit => {
+                Some(format!("  - {} ({} tags)", image, update.searched_amount))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if deep_searches.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "Searched beyond the search limit of {} tags for {} image(s):\n{}",
+            search_limit,
+            deep_searches.len(),
+            deep_searches.join("\n")
+        ))
+    }
+}
+
+fn take_result<E>(results: &mut [Option<JobResult<E>>], index: usize) -> JobResult<E>
+where
+    E: 'static + std::error::Error,
+{
+    results[index].take().unwrap() // Each job belongs to exactly one image.
+}
+
 /// Generates a String that displays the path more prettily than `path.display()`.
 ///
 /// Assumes that the path is canonicalized.
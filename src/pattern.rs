@@ -21,6 +21,27 @@ impl Pattern {
     pub fn breaking_degree(&self) -> usize {
         self.breaking_degree
     }
+
+    /// The names and breaking degrees of the named version groups, in the order they appear.
+    pub fn groups(&self) -> Vec<(&str, usize)> {
+        PatternPart::groups(&self.parts)
+    }
+
+    /// The longest literal part, which every tag matching the pattern contains.
+    ///
+    /// Registries can use it to skip tags that cannot match, e.g. `-alpine` for `<>.<>-alpine`.
+    /// Literals in optional groups or alternatives are not considered, since a tag may lack them.
+    pub fn longest_literal(&self) -> Option<&str> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                PatternPart::Literal(literal) => Some(literal.as_str()),
+                _ => None,
+            })
+            // Prefers the first of equally long literals.
+            .rev()
+            .max_by_key(|literal| literal.len())
+    }
 }
 
 #[derive(Debug, PartialEq, Error)]
@@ -46,31 +67,127 @@ impl std::fmt::Display for Pattern {
         write!(
             f,
             "{}",
-            self.parts
-                .iter()
-                .map(|part| {
-                    use PatternPart::*;
-                    match part {
-                        VersionPart => {
-                            version_part_counter += 1;
-                            if version_part_counter <= self.breaking_degree() {
-                                "<!>".to_string()
-                            } else {
-                                "<>".to_string()
-                            }
-                        }
-                        Literal(literal) => literal.clone(),
-                    }
-                })
-                .join("")
+            display_parts(&self.parts, self.breaking_degree, &mut version_part_counter)
         )
     }
 }
 
+fn display_parts(
+    parts: &[PatternPart],
+    breaking_degree: usize,
+    version_part_counter: &mut usize,
+) -> String {
+    parts
+        .iter()
+        .map(|part| {
+            use PatternPart::*;
+            match part {
+                VersionPart => {
+                    *version_part_counter += 1;
+                    if *version_part_counter <= breaking_degree {
+                        "<!>".to_string()
+                    } else {
+                        "<>".to_string()
+                    }
+                }
+                Literal(literal) => literal.clone(),
+                PreRelease => "<pre>".to_string(),
+                Wildcard { pinned: false } => "<*>".to_string(),
+                Wildcard { pinned: true } => "<=>".to_string(),
+                Optional(parts) => format!(
+                    "[{}]",
+                    display_parts(parts, breaking_degree, version_part_counter)
+                ),
+                Alternatives(spellings) => format!("{{{}}}", spellings.join("|")),
+                Group {
+                    name,
+                    parts,
+                    breaking_degree,
+                } => {
+                    // The version parts of a group are counted separately.
+                    let mut version_part_counter = 0;
+                    format!(
+                        "({}:{})",
+                        name,
+                        display_parts(parts, *breaking_degree, &mut version_part_counter)
+                    )
+                }
+            }
+        })
+        .join("")
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum PatternPart {
     VersionPart,
     Literal(String),
+    /// A pre-release qualifier and its number, e.g. `rc2`, `beta.3` or `alpha1`.
+    PreRelease,
+    /// Any segment without a `-`, e.g. the Alpine version in `14.5.0-alpine3.12`.
+    ///
+    /// It does not affect the comparison of versions. If `pinned`, it has to keep the value of the current tag.
+    Wildcard {
+        pinned: bool,
+    },
+    /// Parts that a tag may omit together, e.g. `[.<>]`. Omitted version parts count as zero.
+    Optional(Vec<PatternPart>),
+    /// One of several spellings of a literal, e.g. `{slim|slim-bullseye}`.
+    Alternatives(Vec<String>),
+    /// Version parts that are compared separately from the others, e.g. the Alpine version in `(alpine:<!>.<>)`.
+    ///
+    /// The `breaking_degree` applies to the group's version parts only.
+    Group {
+        name: String,
+        parts: Vec<PatternPart>,
+        breaking_degree: usize,
+    },
+}
+
+impl PatternPart {
+    /// Counts the version parts outside of named groups, including those in optional groups.
+    fn count_version_parts(parts: &[PatternPart]) -> usize {
+        parts
+            .iter()
+            .map(|part| match part {
+                PatternPart::VersionPart => 1,
+                PatternPart::Optional(parts) => Self::count_version_parts(parts),
+                PatternPart::Literal(_)
+                | PatternPart::PreRelease
+                | PatternPart::Wildcard { .. }
+                | PatternPart::Alternatives(_)
+                | PatternPart::Group { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Counts the pre-release placeholders, including those in optional groups.
+    fn count_pre_releases(parts: &[PatternPart]) -> usize {
+        parts
+            .iter()
+            .map(|part| match part {
+                PatternPart::PreRelease => 1,
+                PatternPart::Optional(parts) | PatternPart::Group { parts, .. } => {
+                    Self::count_pre_releases(parts)
+                }
+                _ => 0,
+            })
+            .sum()
+    }
+
+    fn groups(parts: &[PatternPart]) -> Vec<(&str, usize)> {
+        parts
+            .iter()
+            .flat_map(|part| match part {
+                PatternPart::Group {
+                    name,
+                    breaking_degree,
+                    ..
+                } => vec![(name.as_str(), *breaking_degree)],
+                PatternPart::Optional(parts) => Self::groups(parts),
+                _ => vec![],
+            })
+            .collect()
+    }
 }
 
 mod parser {
@@ -78,10 +195,10 @@ mod parser {
 
     use nom::branch::alt;
     use nom::bytes::complete::{tag, take_while1};
-    use nom::combinator::{all_consuming, opt, recognize};
+    use nom::combinator::{all_consuming, map, opt, recognize, verify};
     use nom::error::ParseError;
-    use nom::multi::many0;
-    use nom::sequence::tuple;
+    use nom::multi::{many0, separated_list1};
+    use nom::sequence::{delimited, separated_pair, tuple};
     use nom::IResult;
 
     pub type Error<'a> = nom::Err<nom::error::VerboseError<&'a str>>;
@@ -90,16 +207,27 @@ mod parser {
     where
         E: ParseError<&'a str>,
     {
-        let (o, (maybe_first, mut breaking, mut compatible)) = all_consuming(tuple((
-            opt(outer_literal),
-            breaking_parts,
-            compatible_parts,
-        )))(i)?;
+        let (o, (maybe_first, mut breaking, mut compatible)) = all_consuming(verify(
+            tuple((opt(outer_literal), breaking_parts, compatible_parts)),
+            |(_, breaking, compatible): &(_, Vec<PatternPart>, Vec<PatternPart>)| {
+                // A tag has at most one pre-release qualifier.
+                let pre_releases = PatternPart::count_pre_releases(breaking)
+                    + PatternPart::count_pre_releases(compatible);
+                let groups = PatternPart::groups(breaking)
+                    .into_iter()
+                    .chain(PatternPart::groups(compatible))
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>();
+                let version_parts = PatternPart::count_version_parts(breaking)
+                    + PatternPart::count_version_parts(compatible);
+                pre_releases <= 1
+                    && groups.iter().unique().count() == groups.len()
+                    // Named groups only complement the other version parts.
+                    && (groups.is_empty() || version_parts > 0)
+            },
+        ))(i)?;
 
-        let breaking_degree = breaking
-            .iter()
-            .filter(|part| matches!(part, PatternPart::VersionPart))
-            .count();
+        let breaking_degree = PatternPart::count_version_parts(&breaking);
         let mut parts = match maybe_first {
             Some(first) => vec![first],
             None => vec![],
@@ -119,14 +247,59 @@ mod parser {
     where
         E: ParseError<&'a str>,
     {
-        many0(alt((inner_literal, breaking_version_part)))(i)
+        many0(alt((
+            inner_literal,
+            alternatives,
+            breaking_version_part,
+            pre_release,
+            wildcard,
+            group,
+            optional(breaking_parts),
+        )))(i)
     }
 
     pub fn compatible_parts<'a, E>(i: &'a str) -> IResult<&'a str, Vec<PatternPart>, E>
     where
         E: ParseError<&'a str>,
     {
-        many0(alt((inner_literal, compatible_version_part)))(i)
+        many0(alt((
+            inner_literal,
+            alternatives,
+            compatible_version_part,
+            pre_release,
+            wildcard,
+            group,
+            optional(compatible_parts),
+        )))(i)
+    }
+
+    /// Parses a non-empty group of `parts` in brackets, e.g. `[.<>]`.
+    pub fn optional<'a, E, F>(parts: F) -> impl FnMut(&'a str) -> IResult<&'a str, PatternPart, E>
+    where
+        E: ParseError<&'a str>,
+        F: FnMut(&'a str) -> IResult<&'a str, Vec<PatternPart>, E>,
+    {
+        map(
+            verify(
+                delimited(tag("["), parts, tag("]")),
+                |parts: &Vec<PatternPart>| !parts.is_empty(),
+            ),
+            PatternPart::Optional,
+        )
+    }
+
+    /// Parses literals separated by `|` in braces, e.g. `{slim|slim-bullseye}`.
+    pub fn alternatives<'a, E>(i: &'a str) -> IResult<&'a str, PatternPart, E>
+    where
+        E: ParseError<&'a str>,
+    {
+        let (o, spellings) = delimited(
+            tag("{"),
+            separated_list1(tag("|"), take_while1(is_inner_literal)),
+            tag("}"),
+        )(i)?;
+        let spellings = spellings.into_iter().map(str::to_string).collect();
+        Ok((o, PatternPart::Alternatives(spellings)))
     }
 
     pub fn inner_literal<'a, E>(i: &'a str) -> IResult<&'a str, PatternPart, E>
@@ -172,6 +345,62 @@ mod parser {
         Ok((o, PatternPart::VersionPart))
     }
 
+    pub fn pre_release<'a, E>(i: &'a str) -> IResult<&'a str, PatternPart, E>
+    where
+        E: ParseError<&'a str>,
+    {
+        let (o, _) = tag("<pre>")(i)?;
+        Ok((o, PatternPart::PreRelease))
+    }
+
+    /// Parses named version parts in parentheses, e.g. `(alpine:<!>.<>)`.
+    ///
+    /// Groups contain at least one version part and cannot be nested.
+    pub fn group<'a, E>(i: &'a str) -> IResult<&'a str, PatternPart, E>
+    where
+        E: ParseError<&'a str>,
+    {
+        let (o, (name, (mut breaking, mut compatible))) = delimited(
+            tag("("),
+            separated_pair(
+                take_while1(is_outer_literal),
+                tag(":"),
+                verify(
+                    tuple((breaking_parts, compatible_parts)),
+                    |(breaking, compatible): &(Vec<PatternPart>, Vec<PatternPart>)| {
+                        PatternPart::count_version_parts(breaking)
+                            + PatternPart::count_version_parts(compatible)
+                            > 0
+                            && PatternPart::groups(breaking).is_empty()
+                            && PatternPart::groups(compatible).is_empty()
+                    },
+                ),
+            ),
+            tag(")"),
+        )(i)?;
+
+        let breaking_degree = PatternPart::count_version_parts(&breaking);
+        breaking.append(&mut compatible);
+        Ok((
+            o,
+            PatternPart::Group {
+                name: name.to_string(),
+                parts: breaking,
+                breaking_degree,
+            },
+        ))
+    }
+
+    pub fn wildcard<'a, E>(i: &'a str) -> IResult<&'a str, PatternPart, E>
+    where
+        E: ParseError<&'a str>,
+    {
+        alt((
+            map(tag("<*>"), |_| PatternPart::Wildcard { pinned: false }),
+            map(tag("<=>"), |_| PatternPart::Wildcard { pinned: true }),
+        ))(i)
+    }
+
     #[cfg(test)]
     mod test {
         use super::*;
@@ -216,6 +445,154 @@ mod parser {
             )
         }
 
+        #[test]
+        fn finds_longest_literal() {
+            let pattern = Pattern::parse("v<>.<>-alpine3.13").unwrap();
+            assert_eq!(pattern.longest_literal(), Some("-alpine3.13"));
+            assert_eq!(Pattern::parse("<>").unwrap().longest_literal(), None);
+        }
+
+        #[test]
+        fn parses_optional_group() {
+            use PatternPart::*;
+            assert_eq!(
+                Pattern::parse("<!>.<>[.<>]"),
+                Ok(Pattern {
+                    parts: vec![
+                        VersionPart,
+                        Literal(".".to_string()),
+                        VersionPart,
+                        Optional(vec![Literal(".".to_string()), VersionPart]),
+                    ],
+                    breaking_degree: 1
+                })
+            )
+        }
+
+        #[test]
+        fn parses_alternatives() {
+            use PatternPart::*;
+            assert_eq!(
+                Pattern::parse("<>-{slim|slim-bullseye}"),
+                Ok(Pattern {
+                    parts: vec![
+                        VersionPart,
+                        Literal("-".to_string()),
+                        Alternatives(vec!["slim".to_string(), "slim-bullseye".to_string()]),
+                    ],
+                    breaking_degree: 0
+                })
+            )
+        }
+
+        #[test]
+        fn parses_pre_release() {
+            use PatternPart::*;
+            assert_eq!(
+                Pattern::parse("<!>.<>[-<pre>]"),
+                Ok(Pattern {
+                    parts: vec![
+                        VersionPart,
+                        Literal(".".to_string()),
+                        VersionPart,
+                        Optional(vec![Literal("-".to_string()), PreRelease]),
+                    ],
+                    breaking_degree: 1
+                })
+            )
+        }
+
+        #[test]
+        fn parses_wildcards() {
+            use PatternPart::*;
+            assert_eq!(
+                Pattern::parse("<!>.<>-alpine<*>-<=>"),
+                Ok(Pattern {
+                    parts: vec![
+                        VersionPart,
+                        Literal(".".to_string()),
+                        VersionPart,
+                        Literal("-alpine".to_string()),
+                        Wildcard { pinned: false },
+                        Literal("-".to_string()),
+                        Wildcard { pinned: true },
+                    ],
+                    breaking_degree: 1
+                })
+            )
+        }
+
+        #[test]
+        fn parses_named_groups() {
+            use PatternPart::*;
+            assert_eq!(
+                Pattern::parse("<!>.<>-alpine(os:<!>.<>)"),
+                Ok(Pattern {
+                    parts: vec![
+                        VersionPart,
+                        Literal(".".to_string()),
+                        VersionPart,
+                        Literal("-alpine".to_string()),
+                        Group {
+                            name: "os".to_string(),
+                            parts: vec![VersionPart, Literal(".".to_string()), VersionPart],
+                            breaking_degree: 1
+                        },
+                    ],
+                    breaking_degree: 1
+                })
+            );
+            assert_eq!(
+                Pattern::parse("<>.<>-(os:<!>.<>)").unwrap().groups(),
+                vec![("os", 1)]
+            );
+        }
+
+        #[test]
+        fn rejects_invalid_groups() {
+            assert!(Pattern::parse("<>-(os:alpine)").is_err());
+            assert!(Pattern::parse("<>-(os:<>-(libc:<>))").is_err());
+            assert!(Pattern::parse("<>-(os:<>)-(os:<>)").is_err());
+            assert!(Pattern::parse("<>-(:<>)").is_err());
+            assert!(Pattern::parse("alpine(os:<>)").is_err());
+        }
+
+        #[test]
+        fn rejects_multiple_pre_releases() {
+            assert!(Pattern::parse("<>-<pre>[-<pre>]").is_err());
+        }
+
+        #[test]
+        fn displays_groups() {
+            for pattern in &[
+                "<!>[.<!>].<>[-{slim|slim-bullseye}]",
+                "<>[.<>[.<>]]",
+                "<>.<><pre>",
+                "<!>.<>-<*>[-<=>]",
+                "<>.<>-(os:<!>.<>)[-(libc:<>)]",
+            ] {
+                assert_eq!(&Pattern::parse(pattern).unwrap().to_string(), pattern);
+            }
+        }
+
+        #[test]
+        fn ignores_literals_a_tag_may_lack() {
+            let pattern = Pattern::parse("<>[-alpine]{-slim|-slim-buster}.0").unwrap();
+            assert_eq!(pattern.longest_literal(), Some(".0"));
+        }
+
+        #[test]
+        fn rejects_empty_groups() {
+            assert!(Pattern::parse("<>[]").is_err());
+            assert!(Pattern::parse("<>{}").is_err());
+            assert!(Pattern::parse("<>[.<>").is_err());
+        }
+
+        #[test]
+        fn rejects_breaking_part_in_compatible_group() {
+            assert!(Pattern::parse("<>[.<!>]").is_err());
+        }
+
         #[test]
         fn rejects_invalid_characters() {
             assert_eq!(
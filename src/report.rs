@@ -1,3 +1,7 @@
+use itertools::{Either, Itertools};
+
+use crate::tag_fetcher::TagRecord;
+use crate::version::{Grade, UpdateType};
 use crate::Update;
 
 #[derive(Debug)]
@@ -5,6 +9,8 @@ pub struct Report<NoUpdate, Update, Error> {
     pub no_updates: Vec<NoUpdate>,
     pub compatible_updates: Vec<Update>,
     pub breaking_updates: Vec<Update>,
+    /// Images whose current tag was pushed again since its digest was recorded.
+    pub rebuilt_updates: Vec<Update>,
     pub failures: Vec<Error>,
 }
 
@@ -18,6 +24,8 @@ impl<N, U, E> Report<N, U, E> {
             BreakingUpdate
         } else if !self.compatible_updates.is_empty() {
             CompatibleUpdate
+        } else if !self.rebuilt_updates.is_empty() {
+            RebuiltUpdate
         } else {
             NoUpdates
         }
@@ -26,6 +34,7 @@ impl<N, U, E> Report<N, U, E> {
 
 pub enum UpdateLevel {
     NoUpdates,
+    RebuiltUpdate,
     CompatibleUpdate,
     BreakingUpdate,
     Failure,
@@ -33,39 +42,144 @@ pub enum UpdateLevel {
 
 type UpdateResult<E> = Result<Update, E>;
 
+/// Splits the tags proposed by `update` into compatible and breaking ones.
+///
+/// If the update was searched per grade, the latest tag of each grade is proposed.
+fn proposed_tags(update: Update) -> (Vec<ProposedTag>, Vec<ProposedTag>) {
+    if !update.per_grade.is_empty() {
+        return update.per_grade.into_iter().partition_map(|graded| {
+            let tag = ProposedTag::new(graded.record, graded.group).with_grade(Some(graded.grade));
+            match graded.update_type {
+                UpdateType::Compatible => Either::Left(tag),
+                UpdateType::Breaking => Either::Right(tag),
+            }
+        });
+    }
+    let Update {
+        compatible,
+        breaking,
+        compatible_group,
+        breaking_group,
+        compatible_grade,
+        breaking_grade,
+        ..
+    } = update;
+    let compatible = compatible
+        .map(|record| ProposedTag::new(record, compatible_group).with_grade(compatible_grade));
+    let breaking =
+        breaking.map(|record| ProposedTag::new(record, breaking_group).with_grade(breaking_grade));
+    (
+        compatible.into_iter().collect(),
+        breaking.into_iter().collect(),
+    )
+}
+
+/// A tag proposed for an image, together with the named version group whose change made it an update, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProposedTag {
+    pub record: TagRecord,
+    pub group: Option<String>,
+    /// How big a step the update is, e.g. a minor update.
+    pub grade: Option<Grade>,
+}
+
+impl ProposedTag {
+    pub fn new(record: TagRecord, group: Option<String>) -> Self {
+        ProposedTag {
+            record,
+            group,
+            grade: None,
+        }
+    }
+
+    pub fn with_grade(mut self, grade: Option<Grade>) -> Self {
+        self.grade = grade;
+        self
+    }
+
+    fn label(&self) -> Option<String> {
+        match (&self.group, &self.grade) {
+            (Some(group), Some(grade)) => Some(format!("{} {} update", group, grade)),
+            (Some(group), None) => Some(format!("{} update", group)),
+            (None, Some(grade)) => Some(format!("{} update", grade)),
+            (None, None) => None,
+        }
+    }
+}
+
+impl From<TagRecord> for ProposedTag {
+    fn from(record: TagRecord) -> Self {
+        Self::new(record, None)
+    }
+}
+
 pub mod dockerfile {
     use super::*;
 
-    use itertools::{Either, Itertools};
-
-    use crate::{display_error, image::Image, Tag, Update};
+    use crate::{
+        display_error,
+        image::{Digest, Image},
+        Update,
+    };
 
     #[derive(Debug)]
     pub struct DockerfileReport<E>
     where
         E: 'static + std::error::Error,
     {
-        pub report: Report<Image, (Image, Tag), (Image, E)>,
+        pub report: Report<Image, (Image, ProposedTag), (Image, E)>,
     }
 
+    /// Formats the update from `current_image` to `new_tag`.
+    ///
+    /// If the current image is pinned to a digest, the new tag is shown with its digest as `<tag>@<digest>`.
+    /// The grade of the update and the named version group that changed are listed first among the details.
     pub fn format_update(
         current_image: &Image,
         version_prefix: &'static str,
-        new_tag: &str,
+        proposed_tag: &ProposedTag,
     ) -> String {
+        let new_tag = &proposed_tag.record;
         let image_name = current_image.name.to_string();
+        let new_reference = match (&current_image.digest, &new_tag.digest) {
+            (Some(_), Some(digest)) => format!("{}@{}", new_tag, digest),
+            _ => new_tag.to_string(),
+        };
+        // The digest is not repeated in the details if the reference already shows it.
+        let metadata = match &current_image.digest {
+            Some(_) => TagRecord {
+                digest: None,
+                ..new_tag.clone()
+            }
+            .describe(),
+            None => new_tag.describe(),
+        };
+        let details = proposed_tag.label().into_iter().chain(metadata).join(", ");
+        let details = if details.is_empty() {
+            details
+        } else {
+            format!(" ({})", details)
+        };
 
         let prefix_width = std::cmp::max(version_prefix.len(), image_name.len());
         format!(
-            "{image_name:>width$}:{current_tag}\n{version_prefix:>width$} {new_tag}",
+            "{image_name:>width$}:{current_reference}\n{version_prefix:>width$} {new_reference}{details}",
             image_name = image_name,
-            current_tag = current_image.tag,
+            current_reference = reference(&current_image.tag, &current_image.digest),
             version_prefix = version_prefix,
-            new_tag = new_tag,
+            new_reference = new_reference,
+            details = details,
             width = prefix_width
         )
     }
 
+    fn reference(tag: &str, digest: &Option<Digest>) -> String {
+        match digest {
+            Some(digest) => format!("{}@{}", tag, digest),
+            None => tag.to_string(),
+        }
+    }
+
     impl<E> DockerfileReport<E>
     where
         E: 'static + std::error::Error,
@@ -80,31 +194,25 @@ pub mod dockerfile {
             let mut no_updates = Vec::new();
             let mut compatible_updates = Vec::new();
             let mut breaking_updates = Vec::new();
+            let mut rebuilt_updates = Vec::new();
 
             for (image, update) in successes {
                 match update {
                     Update {
                         breaking: None,
                         compatible: None,
+                        rebuilt: None,
+                        ..
                     } => no_updates.push(image),
-                    Update {
-                        breaking: None,
-                        compatible: Some(tag),
-                    } => {
-                        compatible_updates.push((image, tag));
-                    }
-                    Update {
-                        breaking: Some(tag),
-                        compatible: None,
-                    } => {
-                        breaking_updates.push((image, tag));
-                    }
-                    Update {
-                        breaking: Some(breaking),
-                        compatible: Some(compatible),
-                    } => {
-                        compatible_updates.push((image.clone(), compatible));
-                        breaking_updates.push((image, breaking));
+                    mut update => {
+                        if let Some(rebuilt) = update.rebuilt.take() {
+                            rebuilt_updates.push((image.clone(), rebuilt.into()));
+                        }
+                        let (compatible, breaking) = proposed_tags(update);
+                        compatible_updates
+                            .extend(compatible.into_iter().map(|tag| (image.clone(), tag)));
+                        breaking_updates
+                            .extend(breaking.into_iter().map(|tag| (image.clone(), tag)));
                     }
                 }
             }
@@ -114,6 +222,7 @@ pub mod dockerfile {
                     no_updates,
                     compatible_updates,
                     breaking_updates,
+                    rebuilt_updates,
                     failures,
                 },
             }
@@ -132,6 +241,12 @@ pub mod dockerfile {
                 .iter()
                 .map(|(image, tag)| format_update(image, "->", tag))
                 .collect::<Vec<_>>();
+            let rebuilt_updates = self
+                .report
+                .rebuilt_updates
+                .iter()
+                .map(|(image, tag)| format_update(image, "~>", tag))
+                .collect::<Vec<_>>();
             let no_updates = self
                 .report
                 .no_updates
@@ -155,6 +270,13 @@ pub mod dockerfile {
                     compatible_updates.join("\n")
                 ));
             }
+            if !rebuilt_updates.is_empty() {
+                output.push(format!(
+                    "{} rebuilt image(s):\n{}",
+                    rebuilt_updates.len(),
+                    rebuilt_updates.join("\n")
+                ));
+            }
             if !no_updates.is_empty() {
                 output.push(format!(
                     "{} without updates:\n{}",
@@ -182,25 +304,158 @@ pub mod dockerfile {
     mod test {
         use super::*;
 
-        use crate::{dockerfile::CheckError, image::ImageName};
+        use crate::{dockerfile::CheckError, image::ImageName, GradedUpdate};
 
         type TestDockerfileResults = Vec<(Image, Result<Update, CheckError>)>;
 
+        #[test]
+        fn reports_rebuilt_images_separately() {
+            let image = Image {
+                name: ImageName::new(None, "ubuntu".to_string()),
+                tag: "20.04".to_string(),
+                digest: None,
+            };
+            let rebuilt = TagRecord {
+                digest: Some("sha256:2004".to_string()),
+                ..TagRecord::from("20.04")
+            };
+            let update = Update {
+                rebuilt: Some(rebuilt.clone()),
+                ..Default::default()
+            };
+            let input: TestDockerfileResults = vec![(image.clone(), Ok(update))];
+
+            let result = DockerfileReport::from(input.into_iter());
+            assert!(result.report.no_updates.is_empty());
+            assert_eq!(result.report.rebuilt_updates, vec![(image, rebuilt.into())]);
+            assert!(matches!(
+                result.report.update_level(),
+                UpdateLevel::RebuiltUpdate
+            ));
+        }
+
+        #[test]
+        fn formats_pinned_update() {
+            let image = Image {
+                name: ImageName::new(None, "ubuntu".to_string()),
+                tag: "14.04".to_string(),
+                digest: Some("sha256:1404".to_string()),
+            };
+            let update = TagRecord {
+                digest: Some("sha256:1405".to_string()),
+                ..TagRecord::from("14.05")
+            };
+
+            assert_eq!(
+                format_update(&image, "->", &update.into()),
+                "ubuntu:14.04@sha256:1404\n    -> 14.05@sha256:1405"
+            );
+        }
+
+        #[test]
+        fn formats_updated_group() {
+            let image = Image {
+                name: ImageName::new(None, "nginx".to_string()),
+                tag: "1.21.6-alpine3.16".to_string(),
+                digest: None,
+            };
+            let update = ProposedTag::new(
+                TagRecord::from("1.21.6-alpine3.17"),
+                Some("alpine".to_string()),
+            );
+
+            assert_eq!(
+                format_update(&image, "->", &update),
+                "nginx:1.21.6-alpine3.16\n   -> 1.21.6-alpine3.17 (alpine update)"
+            );
+        }
+
+        #[test]
+        fn formats_graded_update() {
+            let image = Image {
+                name: ImageName::new(None, "ubuntu".to_string()),
+                tag: "14.04".to_string(),
+                digest: None,
+            };
+            let update =
+                ProposedTag::from(TagRecord::from("14.10")).with_grade(Some(Grade::Part(1)));
+
+            assert_eq!(
+                format_update(&image, "->", &update),
+                "ubuntu:14.04\n    -> 14.10 (minor update)"
+            );
+        }
+
+        #[test]
+        fn lists_updates_per_grade() {
+            let image = Image {
+                name: ImageName::new(None, "node".to_string()),
+                tag: "14.4.0".to_string(),
+                digest: None,
+            };
+            let graded = |name: &str, update_type, grade| GradedUpdate {
+                record: name.into(),
+                update_type,
+                group: None,
+                grade,
+            };
+            let update = Update {
+                compatible: Some("14.6.0".into()),
+                breaking: Some("16.1.0".into()),
+                searched_amount: 7,
+                compatible_grade: Some(Grade::Part(1)),
+                breaking_grade: Some(Grade::Part(0)),
+                per_grade: vec![
+                    graded("14.4.2", UpdateType::Compatible, Grade::Part(2)),
+                    graded("14.6.0", UpdateType::Compatible, Grade::Part(1)),
+                    graded("16.1.0", UpdateType::Breaking, Grade::Part(0)),
+                ],
+                ..Default::default()
+            };
+            let input: TestDockerfileResults = vec![(image.clone(), Ok(update))];
+
+            let report = DockerfileReport::from(input.into_iter()).report;
+            assert_eq!(
+                report.compatible_updates,
+                vec![
+                    (
+                        image.clone(),
+                        ProposedTag::from(TagRecord::from("14.4.2"))
+                            .with_grade(Some(Grade::Part(2)))
+                    ),
+                    (
+                        image.clone(),
+                        ProposedTag::from(TagRecord::from("14.6.0"))
+                            .with_grade(Some(Grade::Part(1)))
+                    ),
+                ]
+            );
+            assert_eq!(
+                report.breaking_updates,
+                vec![(
+                    image,
+                    ProposedTag::from(TagRecord::from("16.1.0")).with_grade(Some(Grade::Part(0)))
+                )]
+            );
+        }
+
         #[test]
         fn generates_dockerfile_report() {
             let success_image = Image {
                 name: ImageName::new(None, "ubuntu".to_string()),
                 tag: "14.04".to_string(),
+                digest: None,
             };
-            let success_tag = "14.05".to_string();
+            let success_tag = TagRecord::from("14.05");
             let success_update = Update {
-                breaking: None,
                 compatible: Some(success_tag.clone()),
+                ..Default::default()
             };
 
             let fail_image = Image {
                 name: ImageName::new(None, "error".to_string()),
                 tag: "1".to_string(),
+                digest: None,
             };
             let fail_error = CheckError::UnspecifiedPattern;
 
@@ -216,7 +471,7 @@ pub mod dockerfile {
                     .compatible_updates
                     .into_iter()
                     .collect::<Vec<_>>(),
-                vec![(success_image, success_tag)],
+                vec![(success_image, success_tag.into())],
             );
             assert_eq!(
                 result
@@ -241,7 +496,6 @@ pub mod docker_compose {
         display_error,
         docker_compose::{BuildContext, ServiceName},
         image::Image,
-        Tag,
     };
 
     // Trait alias
@@ -249,7 +503,10 @@ pub mod docker_compose {
         #[allow(clippy::type_complexity)]
         pub report: Report<
             (ServiceName, BuildContext<(), String, Vec<(Image, ())>>),
-            (ServiceName, BuildContext<Tag, String, Vec<(Image, Tag)>>),
+            (
+                ServiceName,
+                BuildContext<ProposedTag, String, Vec<(Image, ProposedTag)>>,
+            ),
             (
                 ServiceName,
                 Result<BuildContext<E, String, Vec<(Image, E)>>, E>,
@@ -272,6 +529,7 @@ pub mod docker_compose {
             let mut no_updates = Vec::new();
             let mut compatible_updates = Vec::new();
             let mut breaking_updates = Vec::new();
+            let mut rebuilt_updates = Vec::new();
             let mut failures = Vec::new();
 
             for (service, docker_compose_update) in results {
@@ -284,23 +542,23 @@ pub mod docker_compose {
                             Update {
                                 compatible: None,
                                 breaking: None,
+                                rebuilt: None,
+                                ..
                             } => no_updates.push((service, BuildContext::Image(image, ()))),
-                            Update {
-                                compatible,
-                                breaking,
-                            } => {
-                                if let Some(compatible_update) = compatible {
-                                    compatible_updates.push((
-                                        service.clone(),
-                                        BuildContext::Image(image.clone(), compatible_update),
-                                    ));
-                                }
-                                if let Some(breaking_udpate) = breaking {
-                                    breaking_updates.push((
+                            mut update => {
+                                if let Some(rebuilt_update) = update.rebuilt.take() {
+                                    rebuilt_updates.push((
                                         service.clone(),
-                                        BuildContext::Image(image.clone(), breaking_udpate),
+                                        BuildContext::Image(image.clone(), rebuilt_update.into()),
                                     ));
                                 }
+                                let (compatible, breaking) = proposed_tags(update);
+                                compatible_updates.extend(compatible.into_iter().map(|tag| {
+                                    (service.clone(), BuildContext::Image(image.clone(), tag))
+                                }));
+                                breaking_updates.extend(breaking.into_iter().map(|tag| {
+                                    (service.clone(), BuildContext::Image(image.clone(), tag))
+                                }));
                             }
                         },
                     },
@@ -331,6 +589,12 @@ pub mod docker_compose {
                                     BuildContext::Folder(path.clone(), report.breaking_updates),
                                 ));
                             }
+                            if !report.rebuilt_updates.is_empty() {
+                                rebuilt_updates.push((
+                                    service.clone(),
+                                    BuildContext::Folder(path.clone(), report.rebuilt_updates),
+                                ));
+                            }
                             if !report.failures.is_empty() {
                                 failures.push((
                                     service.clone(),
@@ -348,6 +612,7 @@ pub mod docker_compose {
                     no_updates,
                     compatible_updates,
                     breaking_updates,
+                    rebuilt_updates,
                     failures,
                 },
             }
@@ -361,8 +626,8 @@ pub mod docker_compose {
                 .map(|(service, build_context)| match build_context {
                     BuildContext::Image(image, update) => format!(
                         "{service}\n{updates}",
-                        service = display_service_image(service, &image),
-                        updates = display_update(&image, "-!>", update),
+                        service = display_service_image(service, image),
+                        updates = display_update(image, "-!>", update),
                     ),
                     BuildContext::Folder(service_path, updates) => format!(
                         "{service}\n{updates}",
@@ -378,8 +643,8 @@ pub mod docker_compose {
                 .map(|(service, build_context)| match build_context {
                     BuildContext::Image(image, update) => format!(
                         "{service}\n{updates}",
-                        service = display_service_image(service, &image),
-                        updates = display_update(&image, "->", update),
+                        service = display_service_image(service, image),
+                        updates = display_update(image, "->", update),
                     ),
                     BuildContext::Folder(service_path, updates) => format!(
                         "{service}\n{updates}",
@@ -388,12 +653,29 @@ pub mod docker_compose {
                     ),
                 })
                 .collect::<Vec<_>>();
+            let rebuilt_updates = self
+                .report
+                .rebuilt_updates
+                .iter()
+                .map(|(service, build_context)| match build_context {
+                    BuildContext::Image(image, update) => format!(
+                        "{service}\n{updates}",
+                        service = display_service_image(service, image),
+                        updates = display_update(image, "~>", update),
+                    ),
+                    BuildContext::Folder(service_path, updates) => format!(
+                        "{service}\n{updates}",
+                        service = display_service_folder(service, service_path),
+                        updates = display_updates("~>", updates.iter()),
+                    ),
+                })
+                .collect::<Vec<_>>();
             let no_updates = self
                 .report
                 .no_updates
                 .iter()
                 .map(|(service, build_context)| match build_context {
-                    BuildContext::Image(image, ()) => display_service_image(service, &image),
+                    BuildContext::Image(image, ()) => display_service_image(service, image),
                     BuildContext::Folder(service_path, images) => format!(
                         "{service}\n{images}",
                         service = display_service_folder(service, service_path),
@@ -418,6 +700,13 @@ pub mod docker_compose {
                     compatible_updates.join("\n\n")
                 ));
             }
+            if !rebuilt_updates.is_empty() {
+                output.push(format!(
+                    "{} rebuilt image(s):\n{}",
+                    rebuilt_updates.len(),
+                    rebuilt_updates.join("\n\n")
+                ));
+            }
             if !no_updates.is_empty() {
                 output.push(format!(
                     "{} without updates:\n{}",
@@ -442,7 +731,7 @@ pub mod docker_compose {
                     ),
                     Ok(BuildContext::Image(image, error)) => format!(
                         "{service}\n{error}",
-                        service = display_service_image(service, &image),
+                        service = display_service_image(service, image),
                         error = display_error(error)
                     ),
                     Ok(BuildContext::Folder(service_path, errors)) => {
@@ -487,14 +776,14 @@ pub mod docker_compose {
 
     fn display_updates<'a>(
         version_prefix: &'static str,
-        updates: impl Iterator<Item = &'a (Image, String)>,
+        updates: impl Iterator<Item = &'a (Image, ProposedTag)>,
     ) -> String {
         updates
             .map(|(image, update)| display_update(image, version_prefix, update))
             .join("\n")
     }
 
-    fn display_update(image: &Image, version_prefix: &'static str, update: &str) -> String {
+    fn display_update(image: &Image, version_prefix: &'static str, update: &ProposedTag) -> String {
         let output = format_update(image, version_prefix, update);
         let indented_output = output.replace("\n", "\n    ");
         format!("  - {}", indented_output)
@@ -524,16 +813,18 @@ pub mod docker_compose {
             let compatible_image = Image {
                 name: ImageName::new(None, "ubuntu".to_string()),
                 tag: "14.04".to_string(),
+                digest: None,
             };
-            let compatible_tag = "14.05".to_string();
+            let compatible_tag = TagRecord::from("14.05");
             let compatible_update = Update {
-                breaking: None,
                 compatible: Some(compatible_tag.clone()),
+                ..Default::default()
             };
 
             let fail_image = Image {
                 name: ImageName::new(None, "error".to_string()),
                 tag: "1".to_string(),
+                digest: None,
             };
             let fail_error = CheckError::UnspecifiedPattern;
             let fail_error_copy = CheckError::UnspecifiedPattern;
@@ -544,11 +835,12 @@ pub mod docker_compose {
             let breaking_image = Image {
                 name: ImageName::new(None, "alpine".to_string()),
                 tag: "3.8.4".to_string(),
+                digest: None,
             };
-            let breaking_tag = "4.0.2".to_string();
+            let breaking_tag = TagRecord::from("4.0.2");
             let breaking_update = Update {
-                compatible: None,
                 breaking: Some(breaking_tag.clone()),
+                ..Default::default()
             };
 
             let fail_service = "debian".to_string();
@@ -560,17 +852,19 @@ pub mod docker_compose {
             let node_image = Image {
                 name: ImageName::new(None, "node".to_string()),
                 tag: "14.4.0".to_string(),
+                digest: None,
             };
-            let node_compatible_tag = "14.5.0".to_string();
+            let node_compatible_tag = TagRecord::from("14.5.0");
             let node_compatible_update = Update {
                 compatible: Some(node_compatible_tag.clone()),
-                breaking: None,
+                ..Default::default()
             };
 
             let image_fail_service = "python".to_string();
             let image_fail_image = Image {
                 name: ImageName::new(None, "python".to_string()),
                 tag: "3.8.3".to_string(),
+                digest: None,
             };
             let image_fail_error = CheckError::UnspecifiedPattern;
             let image_fail_error_copy = CheckError::UnspecifiedPattern;
@@ -615,12 +909,12 @@ pub mod docker_compose {
                         ubuntu_service.clone(),
                         BuildContext::Folder(
                             ubuntu_path.clone(),
-                            vec![(compatible_image, compatible_tag)]
+                            vec![(compatible_image, compatible_tag.into())]
                         )
                     ),
                     (
                         node_service,
-                        BuildContext::Image(node_image, node_compatible_tag)
+                        BuildContext::Image(node_image, node_compatible_tag.into())
                     )
                 ]
             );
@@ -645,7 +939,7 @@ pub mod docker_compose {
                 result.report.breaking_updates,
                 vec![(
                     alpine_service,
-                    BuildContext::Folder(alpine_path, vec![(breaking_image, breaking_tag)])
+                    BuildContext::Folder(alpine_path, vec![(breaking_image, breaking_tag.into())])
                 )]
             )
         }
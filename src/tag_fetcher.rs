@@ -1,13 +1,30 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use serde::Deserialize;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::image::ImageName;
+use crate::credentials::{Credentials, DockerConfig};
+use crate::image::{Digest, ImageName, Platform};
+use http::HttpClient;
+use retry::{Quota, RetryPolicy};
+
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod cache;
+pub mod http;
+pub mod memo;
+pub mod oci;
+pub mod plugin;
+pub mod retry;
+pub mod snapshot;
 
 /// Enables fetching of tags belonging to an image.
 pub trait TagFetcher {
-    type TagIter: IntoIterator<Item = Result<Tag, Self::FetchError>>;
+    type TagIter: IntoIterator<Item = Result<TagRecord, Self::FetchError>>;
     type FetchError: std::error::Error;
 
     /// Constructs a fallible iterator over the `image`'s tags ordered
@@ -22,12 +39,48 @@ pub trait TagFetcher {
     /// as the next iterator item.
     ///
     fn fetch(&self, image: &ImageName) -> Self::TagIter;
+
+    /// Like `fetch`, but allows the `TagFetcher` to skip tags that do not contain `substring`.
+    ///
+    /// This saves requests if the registry can filter the tags, e.g. by a pattern's literal.
+    /// Fetchers that cannot filter return all tags, so the tags still have to be checked.
+    fn fetch_containing(&self, image: &ImageName, _substring: &str) -> Self::TagIter {
+        self.fetch(image)
+    }
+
+    /// Looks up the digest of the manifest that the `image`'s `tag` currently points to.
+    ///
+    /// This is used to pin updates of images that are referenced by digest.
+    /// Returns `None` if the `TagFetcher` cannot resolve digests.
+    fn digest(&self, _image: &ImageName, _tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        Ok(None)
+    }
+
+    /// Whether the `image`'s tags are really ordered from newest to oldest.
+    ///
+    /// Registries that do not expose when a tag was pushed can only approximate
+    /// this order, so updates are then selected by version instead, see `SearchOrder::Version`.
+    fn lists_newest_first(&self, _image: &ImageName) -> bool {
+        true
+    }
 }
 
 /// Fetches tags from DockerHub.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct DockerHubTagFetcher {
     search_limit: usize,
+    /// How many tags are requested at once.
+    page_size: usize,
+    /// The URLs of DockerHub's API or its mirrors, in the order they are tried.
+    base_urls: Arc<Vec<String>>,
+    client: HttpClient,
+    /// Sends the requests of `AsyncTagFetcher::fetch`.
+    #[cfg(feature = "async")]
+    async_client: reqwest::Client,
+    login: Arc<Login>,
+    retry_policy: RetryPolicy,
+    /// Shared between all fetches, because DockerHub limits the requests per user or IP address.
+    quota: Arc<Quota>,
 }
 
 // API types from DockerHub
@@ -41,17 +94,302 @@ struct Response {
 #[derive(Debug, Deserialize)]
 struct TagInfo {
     name: String,
+    last_updated: Option<String>,
+    digest: Option<Digest>,
+    full_size: Option<u64>,
+    #[serde(default)]
+    images: Vec<PlatformInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlatformInfo {
+    os: Option<String>,
+    architecture: Option<String>,
+    variant: Option<String>,
+}
+
+impl From<TagInfo> for TagRecord {
+    fn from(info: TagInfo) -> Self {
+        TagRecord {
+            name: info.name,
+            last_updated: info.last_updated,
+            digest: info.digest,
+            size: info.full_size,
+            platforms: info
+                .images
+                .into_iter()
+                .filter_map(|image| {
+                    Some(Platform {
+                        os: image.os?,
+                        architecture: image.architecture?,
+                        variant: image.variant.filter(|variant| !variant.is_empty()),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginResponse {
+    token: String,
 }
 
 type Tag = String;
 
+/// A tag together with the metadata the registry provides about it.
+///
+/// Registries that only list tag names, like those implementing the Registry
+/// HTTP API v2, yield records without metadata, see `TagRecord::from`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagRecord {
+    pub name: Tag,
+    /// When the tag was last pushed, as an RFC 3339 timestamp like `2021-02-04T18:32:55.123456Z`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Digest>,
+    /// The compressed size in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<Platform>,
+}
+
+impl TagRecord {
+    /// Summarizes the metadata, e.g. `2021-02-04, sha256:3c6cfb5eae1f, linux/amd64, linux/arm64/v8`.
+    ///
+    /// Returns `None` if there is no metadata.
+    pub fn describe(&self) -> Option<String> {
+        let date = self
+            .last_updated
+            .as_ref()
+            // Only keeps the date of RFC 3339 timestamps.
+            .map(|timestamp| timestamp.split('T').next().unwrap_or(timestamp).to_string());
+        let digest = self
+            .digest
+            .as_ref()
+            .map(|digest| match digest.split_once(':') {
+                // The digest is not validated, so it may contain multi-byte characters.
+                Some((algorithm, hex)) => {
+                    format!("{}:{}", algorithm, hex.chars().take(12).collect::<String>())
+                }
+                None => digest.clone(),
+            });
+        let details = date
+            .into_iter()
+            .chain(digest)
+            .chain(self.platforms.iter().map(Platform::to_string))
+            .collect::<Vec<_>>();
+        if details.is_empty() {
+            None
+        } else {
+            Some(details.join(", "))
+        }
+    }
+}
+
+impl From<Tag> for TagRecord {
+    fn from(name: Tag) -> Self {
+        TagRecord {
+            name,
+            last_updated: None,
+            digest: None,
+            size: None,
+            platforms: Vec::new(),
+        }
+    }
+}
+
+impl From<&str> for TagRecord {
+    fn from(name: &str) -> Self {
+        Self::from(name.to_string())
+    }
+}
+
+impl PartialEq<&str> for TagRecord {
+    fn eq(&self, other: &&str) -> bool {
+        self.name == *other
+    }
+}
+
+impl AsRef<str> for TagRecord {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl std::fmt::Display for TagRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub const DOCKER_HUB_URL: &str = "https://hub.docker.com";
+
+impl Default for DockerHubTagFetcher {
+    fn default() -> Self {
+        DockerHubTagFetcher {
+            search_limit: 100,
+            page_size: DEFAULT_PAGE_SIZE,
+            base_urls: Arc::new(vec![DOCKER_HUB_URL.to_string()]),
+            client: HttpClient::default(),
+            #[cfg(feature = "async")]
+            async_client: http::default_async_client(),
+            login: Arc::default(),
+            retry_policy: RetryPolicy::default(),
+            quota: Arc::default(),
+        }
+    }
+}
+
 impl DockerHubTagFetcher {
     pub fn new() -> Self {
-        DockerHubTagFetcher { search_limit: 100 }
+        Self::with_search_limit(100)
     }
 
     pub fn with_search_limit(search_limit: usize) -> Self {
-        DockerHubTagFetcher { search_limit }
+        DockerHubTagFetcher {
+            search_limit,
+            ..Self::default()
+        }
+    }
+
+    /// Logs in with `credentials` instead of fetching anonymously, e.g. to access private repositories.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.login = Arc::new(Login::new(CredentialSource::Fixed(credentials)));
+        self
+    }
+
+    /// Logs in with the credentials for DockerHub stored in `config`, if there are any.
+    ///
+    /// The credentials are only looked up once the first tag is fetched.
+    pub fn with_docker_config(mut self, config: Arc<DockerConfig>) -> Self {
+        self.login = Arc::new(Login::new(CredentialSource::DockerConfig(config)));
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sends the requests with `client`, e.g. one built from an `http::HttpConfig`.
+    pub fn with_client(mut self, client: impl Into<HttpClient>) -> Self {
+        self.client = client.into();
+        self
+    }
+
+    /// Sends the requests of the async fetches with `client`, e.g. one built by `http::HttpConfig::build_async_client`.
+    #[cfg(feature = "async")]
+    pub fn with_async_client(mut self, client: reqwest::Client) -> Self {
+        self.async_client = client;
+        self
+    }
+
+    /// Requests `page_size` tags at once, which DockerHub limits to 100.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is zero.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        assert!(page_size > 0, "The page size must not be zero.");
+        self.page_size = page_size;
+        self
+    }
+
+    /// Fetches from a server implementing DockerHub's API at `base_url` instead, e.g. a mirror.
+    pub fn with_base_url(self, base_url: impl Into<String>) -> Self {
+        self.with_mirrors(vec![base_url.into()])
+    }
+
+    /// Tries each of the `base_urls` in turn, moving on to the next one when a request fails.
+    ///
+    /// # Panics
+    /// Panics if `base_urls` is empty.
+    pub fn with_mirrors(mut self, base_urls: Vec<String>) -> Self {
+        assert!(!base_urls.is_empty(), "At least one base URL is required.");
+        self.base_urls = Arc::new(
+            base_urls
+                .into_iter()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .collect(),
+        );
+        self
+    }
+}
+
+/// Logs in to each of DockerHub's mirrors once and shares its token between all fetches.
+#[derive(Debug, Default)]
+struct Login {
+    source: CredentialSource,
+    /// The tokens by the base URL they were issued by, `None` for anonymous access.
+    ///
+    /// A mirror is missing until its login succeeded.
+    tokens: Mutex<HashMap<String, Option<String>>>,
+}
+
+#[derive(Debug, Default)]
+enum CredentialSource {
+    #[default]
+    Anonymous,
+    Fixed(Credentials),
+    DockerConfig(Arc<DockerConfig>),
+}
+
+impl Login {
+    fn new(source: CredentialSource) -> Self {
+        Login {
+            source,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn token(
+        &self,
+        client: &HttpClient,
+        base_url: &str,
+    ) -> Result<Option<String>, DockerHubTagFetcherError> {
+        let mut tokens = self.tokens.lock().unwrap();
+        if let Some(token) = tokens.get(base_url) {
+            return Ok(token.clone());
+        }
+        let token = match self.credentials() {
+            Some(credentials) => Some(Self::log_in(client, base_url, &credentials)?),
+            None => None,
+        };
+        tokens.insert(base_url.to_string(), token.clone());
+        Ok(token)
+    }
+
+    fn credentials(&self) -> Option<Credentials> {
+        match &self.source {
+            CredentialSource::Anonymous => None,
+            CredentialSource::Fixed(credentials) => Some(credentials.clone()),
+            CredentialSource::DockerConfig(config) => {
+                config.credentials_or_anonymous(crate::image::DOCKER_HUB)
+            }
+        }
+    }
+
+    fn log_in(
+        client: &HttpClient,
+        base_url: &str,
+        credentials: &Credentials,
+    ) -> Result<String, DockerHubTagFetcherError> {
+        log::info!("Logging in to DockerHub as {}...", credentials.username);
+        let request =
+            client
+                .post(&format!("{}/v2/users/login/", base_url))
+                .json(&serde_json::json!({
+                    "username": credentials.username,
+                    "password": credentials.password,
+                }));
+        let response = client
+            .send(request)
+            .and_then(|response| Ok(response.error_for_status()?))
+            .and_then(|response| Ok(response.json::<LoginResponse>()?))
+            .map_err(DockerHubTagFetcherError::LoginFailed)?;
+        Ok(response.token)
     }
 }
 
@@ -60,117 +398,291 @@ impl TagFetcher for DockerHubTagFetcher {
     type FetchError = DockerHubTagFetcherError;
 
     fn fetch(&self, name: &ImageName) -> Self::TagIter {
-        DockerHubTagIterator::new(name).take(self.search_limit)
+        DockerHubTagIterator::new(self, name, None).take(self.search_limit)
+    }
+
+    /// Uses DockerHub's `name` filter, which only lists tags containing `substring`.
+    ///
+    /// The search limit then applies to the filtered tags.
+    fn fetch_containing(&self, name: &ImageName, substring: &str) -> Self::TagIter {
+        DockerHubTagIterator::new(self, name, name_filter(substring)).take(self.search_limit)
+    }
+
+    /// Looks up the digest via DockerHub's endpoint for a single tag.
+    fn digest(&self, name: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        DockerHubTagIterator::new(self, name, None)
+            .fetch_json::<TagInfo>(|pages| Some(pages.tag_url(tag)))
+            .transpose()
+            .map(|info| info.and_then(|info| info.digest))
+    }
+}
+
+/// The value of DockerHub's `name` filter for tags containing `substring`, or `None` if it cannot be filtered by.
+fn name_filter(substring: &str) -> Option<String> {
+    // Only URL-safe characters are passed on unencoded, as pattern literals consist of them.
+    let is_url_safe = |c: char| c.is_ascii_alphanumeric() || "-._~".contains(c);
+    if substring.is_empty() || !substring.chars().all(is_url_safe) {
+        None
+    } else {
+        Some(substring.to_string())
     }
 }
 
-const FETCH_AMOUNT: usize = 25;
+const DEFAULT_PAGE_SIZE: usize = 100;
 
 pub struct DockerHubTagIterator {
+    pages: Pages,
+    client: HttpClient,
+}
+
+/// The state of paging through an image's tags on DockerHub or its mirrors,
+/// no matter whether the pages are fetched with or without blocking.
+struct Pages {
     image_name: ImageName,
+    /// Whether only the tags matching a name filter are listed.
+    filtered: bool,
+    base_urls: Arc<Vec<String>>,
+    /// The index of the base URL currently fetched from.
+    mirror: usize,
+    login: Arc<Login>,
+    retry_policy: RetryPolicy,
+    quota: Arc<Quota>,
     /// The tags of the current page.
-    fetched: VecDeque<Tag>,
+    fetched: VecDeque<TagRecord>,
     current_page: CurrentPage,
 }
 
 enum CurrentPage {
-    First,
+    /// The query of the first page, e.g. `page_size=100&page=1&ordering=last_updated`.
+    First(String),
+    /// Either a full URL, or a path relative to the base URL.
     Next(String),
     End,
 }
 
 impl CurrentPage {
-    fn get_url(&self, image: &ImageName) -> Option<String> {
+    fn get_url(&self, base_url: &str, image: &ImageName) -> Option<String> {
         use CurrentPage::*;
         match self {
-            First => Some(format!(
-                "https://hub.docker.com/v2/repositories/{image}/tags/?page_size={amount}&page={page}&ordering=last_updated",
-                image=Self::format_name_for_url(&image),
-                amount=FETCH_AMOUNT,
-                page=1
+            First(query) => Some(format!(
+                "{base_url}/v2/repositories/{image}/tags/?{query}",
+                base_url = base_url,
+                image = Self::format_name_for_url(image),
+                query = query
             )),
+            Next(page) if page.starts_with('/') => Some(format!("{}{}", base_url, page)),
             Next(page) => Some(page.clone()),
             End => None,
         }
     }
 
+    /// Keeps the next page relative to `base_url`, so that it can be fetched from a different mirror.
+    fn next(base_url: &str, next_page: String) -> CurrentPage {
+        match next_page.strip_prefix(base_url) {
+            Some(path) if path.starts_with('/') => CurrentPage::Next(path.to_string()),
+            _ => CurrentPage::Next(next_page),
+        }
+    }
+
     fn format_name_for_url(name: &ImageName) -> String {
-        match name {
-            ImageName::Official { image } => format!("library/{image}", image = image),
-            ImageName::User { user, image } => {
-                format!("{user}/{image}", user = user, image = image)
+        name.repository()
+    }
+}
+
+impl Pages {
+    /// Lists the tags of `image_name`, only those containing `name_filter` if given.
+    fn new(
+        fetcher: &DockerHubTagFetcher,
+        image_name: &ImageName,
+        name_filter: Option<String>,
+    ) -> Self {
+        let mut query = format!(
+            "page_size={amount}&page={page}&ordering=last_updated",
+            amount = fetcher.page_size,
+            page = 1
+        );
+        if let Some(name_filter) = &name_filter {
+            query.push_str("&name=");
+            query.push_str(name_filter);
+        }
+        Pages {
+            fetched: VecDeque::with_capacity(fetcher.page_size),
+            image_name: image_name.clone(),
+            filtered: name_filter.is_some(),
+            base_urls: Arc::clone(&fetcher.base_urls),
+            mirror: 0,
+            login: Arc::clone(&fetcher.login),
+            retry_policy: fetcher.retry_policy,
+            quota: Arc::clone(&fetcher.quota),
+            current_page: CurrentPage::First(query),
+        }
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_urls[self.mirror]
+    }
+
+    /// The URL of the current page on the current mirror, or `None` after the last page.
+    fn page_url(&self) -> Option<String> {
+        self.current_page.get_url(self.base_url(), &self.image_name)
+    }
+
+    /// The URL of the `tag`'s details on the current mirror.
+    fn tag_url(&self, tag: &str) -> String {
+        format!(
+            "{base_url}/v2/repositories/{image}/tags/{tag}",
+            base_url = self.base_url(),
+            image = CurrentPage::format_name_for_url(&self.image_name),
+            tag = tag
+        )
+    }
+
+    /// Moves on to the next mirror after the current one failed with `error`, returning whether there is one.
+    ///
+    /// Errors that other mirrors would report as well, like a missing image, are not retried.
+    fn fall_back(&mut self, error: &DockerHubTagFetcherError) -> bool {
+        if error.is_mirror_failure() && self.mirror + 1 < self.base_urls.len() {
+            log::warn!("Failed to fetch tags from {}: {}", self.base_url(), error);
+            self.mirror += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn check_status(
+        &self,
+        status: StatusCode,
+        headers: &reqwest::header::HeaderMap,
+    ) -> Result<(), DockerHubTagFetcherError> {
+        use DockerHubTagFetcherError::*;
+        let image = self.image_name.clone();
+        match status {
+            status if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED => Err(Unauthorized(image)),
+            StatusCode::FORBIDDEN => Err(Forbidden(image)),
+            StatusCode::NOT_FOUND => Err(NotFound(image)),
+            StatusCode::TOO_MANY_REQUESTS => Err(RateLimited {
+                retry_after: retry::retry_after(headers),
+            }),
+            status if status.is_server_error() => Err(ServerError(status)),
+            status => Err(UnexpectedStatus(status)),
+        }
+    }
+
+    /// Keeps the tags of the fetched `page`, returning the first one.
+    fn receive(&mut self, page: Response) -> Result<Option<TagRecord>, DockerHubTagFetcherError> {
+        log::info!("Fetch was successful.");
+
+        let mut tags = page
+            .results
+            .into_iter()
+            .map(TagRecord::from)
+            .collect::<VecDeque<_>>();
+
+        // If the image name is invalid, we will get a 200 OK, but
+        // with an empty tag list. For details, see https://github.com/Y0hy0h/uptag/issues/37
+        // With a name filter, an empty list only means that no tag matched.
+        if let CurrentPage::First(_) = self.current_page {
+            if tags.is_empty() && !self.filtered {
+                return Err(DockerHubTagFetcherError::EmptyTags(self.image_name.clone()));
+            }
+        }
+
+        let next = tags.pop_front();
+        self.fetched = tags;
+
+        match page.next {
+            Some(next_page) => {
+                self.current_page = CurrentPage::next(self.base_url(), next_page);
+            }
+            None => {
+                self.current_page = CurrentPage::End;
             }
         }
+
+        Ok(next)
     }
 }
 
 impl DockerHubTagIterator {
-    fn new(image_name: &ImageName) -> Self {
+    fn new(
+        fetcher: &DockerHubTagFetcher,
+        image_name: &ImageName,
+        name_filter: Option<String>,
+    ) -> Self {
         DockerHubTagIterator {
-            fetched: VecDeque::with_capacity(FETCH_AMOUNT),
-            image_name: image_name.clone(),
-            current_page: CurrentPage::First,
+            pages: Pages::new(fetcher, image_name, name_filter),
+            client: fetcher.client.clone(),
         }
     }
-}
 
-type DockerHubTagIteratorError = reqwest::Error;
+    fn get(&self, url: &str) -> Result<reqwest::blocking::Response, DockerHubTagFetcherError> {
+        let pages = &self.pages;
+        let token = pages.login.token(&self.client, pages.base_url())?;
+        pages
+            .quota
+            .acquire(&pages.retry_policy)
+            .map_err(|exhausted| DockerHubTagFetcherError::QuotaExhausted {
+                reset_in: exhausted.reset_in,
+            })?;
+        let response = pages.retry_policy.send(&self.client, || {
+            let request = self.client.get(url);
+            match &token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+        })?;
+        pages.quota.update(response.headers());
+        pages.check_status(response.status(), response.headers())?;
+        Ok(response)
+    }
 
-impl Iterator for DockerHubTagIterator {
-    type Item = Result<Tag, DockerHubTagFetcherError>;
+    /// Fetches the current page, falling back to the next mirror if a mirror fails.
+    fn fetch_page(&mut self) -> Option<Result<Response, DockerHubTagFetcherError>> {
+        self.fetch_json(Pages::page_url)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if !self.fetched.is_empty() {
-            self.fetched.pop_front().map(Ok)
-        } else {
-            let url = self.current_page.get_url(&self.image_name)?;
+    /// Fetches the URL that `url_for` builds for the current mirror, falling back to the next mirror if a mirror fails.
+    fn fetch_json<R>(
+        &mut self,
+        url_for: impl Fn(&Pages) -> Option<String>,
+    ) -> Option<Result<R, DockerHubTagFetcherError>>
+    where
+        R: serde::de::DeserializeOwned,
+    {
+        loop {
+            let url = url_for(&self.pages)?;
 
             log::info!(
                 "Fetching tags for {image}:\n{url}",
-                image = self.image_name,
+                image = self.pages.image_name,
                 url = url
             );
-            let response_result = reqwest::blocking::get(&url);
-            response_result
-                .and_then(|response| {
-                    log::debug!("Received response with status `{}`.", response.status());
-                    log::debug!("Reading JSON body...");
-                    response.json::<Response>()
-                })
-                .map_err(DockerHubTagFetcherError::FetchError)
-                .and_then(|response| {
-                    log::info!("Fetch was successful.");
-
-                    let mut tags = response
-                        .results
-                        .into_iter()
-                        .map(|info| info.name)
-                        .collect::<VecDeque<_>>();
-
-                    // If the image name is invalid, we will get a 200 OK, but
-                    // with an empty tag list. For details, see https://github.com/Y0hy0h/uptag/issues/37
-                    if let CurrentPage::First = self.current_page {
-                        if tags.is_empty() {
-                            return Err(DockerHubTagFetcherError::EmptyTags(
-                                self.image_name.clone(),
-                            ));
-                        }
-                    }
+            let result = self.get(&url).and_then(|response| {
+                log::debug!("Received response with status `{}`.", response.status());
+                log::debug!("Reading JSON body...");
+                Ok(response.json::<R>()?)
+            });
+            match result {
+                Err(error) if self.pages.fall_back(&error) => {}
+                result => return Some(result),
+            }
+        }
+    }
+}
 
-                    let next = tags.pop_front();
-                    self.fetched = tags;
+type DockerHubTagIteratorError = reqwest::Error;
 
-                    match response.next {
-                        Some(next_page) => {
-                            self.current_page = CurrentPage::Next(next_page);
-                        }
-                        None => {
-                            self.current_page = CurrentPage::End;
-                        }
-                    }
+impl Iterator for DockerHubTagIterator {
+    type Item = Result<TagRecord, DockerHubTagFetcherError>;
 
-                    Ok(next)
-                })
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(tag) = self.pages.fetched.pop_front() {
+            Some(Ok(tag))
+        } else {
+            self.fetch_page()?
+                .and_then(|page| self.pages.receive(page))
                 .transpose()
         }
     }
@@ -182,6 +694,238 @@ pub enum DockerHubTagFetcherError {
     FetchError(#[from] DockerHubTagIteratorError),
     #[error("The tag list was empty (this might indicate that `{0}` is not a valid image name)")]
     EmptyTags(ImageName),
+    #[error("Failed to log in to DockerHub")]
+    LoginFailed(#[source] http::SendError),
+    #[error(transparent)]
+    Send(#[from] http::SendError),
+    #[error("Not authorized to access `{0}` (private images require `docker login`)")]
+    Unauthorized(ImageName),
+    #[error("Access to `{0}` was denied")]
+    Forbidden(ImageName),
+    #[error("The image `{0}` does not exist on DockerHub")]
+    NotFound(ImageName),
+    #[error("DockerHub's rate limit was exceeded{}", display_wait("retry in", *.retry_after))]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("Stopped before exhausting DockerHub's rate limit{}", display_wait("resets in", *.reset_in))]
+    QuotaExhausted { reset_in: Option<Duration> },
+    #[error("DockerHub failed with status `{0}`")]
+    ServerError(StatusCode),
+    #[error("DockerHub responded with unexpected status `{0}`")]
+    UnexpectedStatus(StatusCode),
+}
+
+impl DockerHubTagFetcherError {
+    /// Whether the error is specific to the mirror, i.e. it could not be reached, failed or was rate limited.
+    fn is_mirror_failure(&self) -> bool {
+        use DockerHubTagFetcherError::*;
+        match self {
+            FetchError(error)
+            | LoginFailed(http::SendError::Request(error))
+            | Send(http::SendError::Request(error)) => match error.status() {
+                Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+                // Invalid responses are not a transport error.
+                None => !error.is_decode(),
+            },
+            RateLimited { .. } | ServerError(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn display_wait(description: &str, wait: Option<Duration>) -> String {
+    match wait {
+        Some(wait) => format!(" ({} {}s)", description, wait.as_secs()),
+        None => String::new(),
+    }
+}
+
+/// Fetches tags from the registry that hosts the image.
+///
+/// Images on DockerHub are fetched via DockerHub's API, images on other
+/// registries via the Registry HTTP API v2. Registries on `localhost` are
+/// accessed via HTTP, all others via HTTPS. Registries that a plugin was
+/// configured for are accessed via the plugin instead, see `with_plugin`.
+#[derive(Debug)]
+pub struct RegistryTagFetcher {
+    docker_hub: DockerHubTagFetcher,
+    search_limit: usize,
+    client: HttpClient,
+    docker_config: Option<Arc<DockerConfig>>,
+    /// The credentials looked up so far, by registry.
+    credentials: Mutex<HashMap<String, Option<Credentials>>>,
+    /// The plugins by the registry they fetch from.
+    plugins: HashMap<String, plugin::PluginTagFetcher>,
+}
+
+impl RegistryTagFetcher {
+    pub fn new() -> Self {
+        Self::with_search_limit(100)
+    }
+
+    pub fn with_search_limit(search_limit: usize) -> Self {
+        RegistryTagFetcher {
+            docker_hub: DockerHubTagFetcher::with_search_limit(search_limit),
+            search_limit,
+            client: HttpClient::default(),
+            docker_config: None,
+            credentials: Mutex::new(HashMap::new()),
+            plugins: HashMap::new(),
+        }
+    }
+
+    /// Fetches the tags of images on `registry`, e.g. `registry.example.com`, by running `program`.
+    ///
+    /// See `plugin::PluginTagFetcher` for the protocol. Use `docker.io` for DockerHub.
+    pub fn with_plugin(mut self, registry: impl Into<String>, program: impl Into<PathBuf>) -> Self {
+        let plugin = plugin::PluginTagFetcher::with_search_limit(program, self.search_limit);
+        self.plugins.insert(registry.into(), plugin);
+        self
+    }
+
+    /// Authenticates with the credentials stored in `config`, as `docker login` does.
+    pub fn with_docker_config(mut self, config: DockerConfig) -> Self {
+        let config = Arc::new(config);
+        self.docker_hub = self.docker_hub.with_docker_config(Arc::clone(&config));
+        self.docker_config = Some(config);
+        self
+    }
+
+    /// Fetches images on DockerHub from each of the `base_urls` in turn, e.g. from mirrors.
+    ///
+    /// See `DockerHubTagFetcher::with_mirrors`.
+    pub fn with_docker_hub_mirrors(mut self, base_urls: Vec<String>) -> Self {
+        self.docker_hub = self.docker_hub.with_mirrors(base_urls);
+        self
+    }
+
+    /// Sends all requests with `client`, e.g. one built from an `http::HttpConfig`.
+    pub fn with_client(mut self, client: impl Into<HttpClient>) -> Self {
+        let client = client.into();
+        self.docker_hub = self.docker_hub.with_client(client.clone());
+        self.client = client;
+        self
+    }
+
+    /// Configures how requests to DockerHub are retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.docker_hub = self.docker_hub.with_retry_policy(retry_policy);
+        self
+    }
+
+    /// See `DockerHubTagFetcher::with_page_size`.
+    pub fn with_docker_hub_page_size(mut self, page_size: usize) -> Self {
+        self.docker_hub = self.docker_hub.with_page_size(page_size);
+        self
+    }
+
+    fn credentials_for(&self, registry: &str) -> Option<Credentials> {
+        let config = self.docker_config.as_ref()?;
+        let mut known = self.credentials.lock().unwrap();
+        if let Some(credentials) = known.get(registry) {
+            return credentials.clone();
+        }
+        let credentials = config.credentials_or_anonymous(registry);
+        known.insert(registry.to_string(), credentials.clone());
+        credentials
+    }
+
+    fn oci_fetcher(&self, image: &ImageName) -> oci::OciTagFetcher {
+        let credentials = self.credentials_for(image.registry());
+        let mut fetcher = oci::OciTagFetcher::with_search_limit(
+            Self::registry_url(image.registry()),
+            self.search_limit,
+        )
+        .with_client(self.client.clone());
+        if let Some(credentials) = credentials {
+            fetcher = fetcher.with_credentials(credentials);
+        }
+        fetcher
+    }
+
+    fn registry_url(registry: &str) -> String {
+        let host = registry.split(':').next().unwrap_or(registry);
+        if host == "localhost" || host == "127.0.0.1" {
+            format!("http://{}", registry)
+        } else {
+            format!("https://{}", registry)
+        }
+    }
+}
+
+impl Default for RegistryTagFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TagFetcher for RegistryTagFetcher {
+    type TagIter = RegistryTagIterator;
+    type FetchError = RegistryTagFetcherError;
+
+    fn fetch(&self, image: &ImageName) -> Self::TagIter {
+        if let Some(plugin) = self.plugins.get(image.registry()) {
+            RegistryTagIterator::Plugin(plugin.fetch(image))
+        } else if image.is_docker_hub() {
+            RegistryTagIterator::DockerHub(self.docker_hub.fetch(image))
+        } else {
+            RegistryTagIterator::Oci(self.oci_fetcher(image).fetch(image))
+        }
+    }
+
+    /// Only images on DockerHub are filtered, since the Registry HTTP API v2 cannot filter tags.
+    fn fetch_containing(&self, image: &ImageName, substring: &str) -> Self::TagIter {
+        if image.is_docker_hub() && !self.plugins.contains_key(image.registry()) {
+            RegistryTagIterator::DockerHub(self.docker_hub.fetch_containing(image, substring))
+        } else {
+            self.fetch(image)
+        }
+    }
+
+    fn digest(&self, image: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        if let Some(plugin) = self.plugins.get(image.registry()) {
+            Ok(plugin.digest(image, tag)?)
+        } else if image.is_docker_hub() {
+            Ok(self.docker_hub.digest(image, tag)?)
+        } else {
+            Ok(self.oci_fetcher(image).digest(image, tag)?)
+        }
+    }
+
+    fn lists_newest_first(&self, image: &ImageName) -> bool {
+        self.plugins.contains_key(image.registry()) || image.is_docker_hub()
+    }
+}
+
+pub enum RegistryTagIterator {
+    DockerHub(<DockerHubTagFetcher as TagFetcher>::TagIter),
+    Oci(<oci::OciTagFetcher as TagFetcher>::TagIter),
+    Plugin(<plugin::PluginTagFetcher as TagFetcher>::TagIter),
+}
+
+impl Iterator for RegistryTagIterator {
+    type Item = Result<TagRecord, RegistryTagFetcherError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            RegistryTagIterator::DockerHub(iter) => {
+                iter.next().map(|result| result.map_err(Into::into))
+            }
+            RegistryTagIterator::Oci(iter) => iter.next().map(|result| result.map_err(Into::into)),
+            RegistryTagIterator::Plugin(iter) => {
+                iter.next().map(|result| result.map_err(Into::into))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RegistryTagFetcherError {
+    #[error(transparent)]
+    DockerHub(#[from] DockerHubTagFetcherError),
+    #[error(transparent)]
+    Oci(#[from] oci::OciTagFetcherError),
+    #[error(transparent)]
+    Plugin(#[from] plugin::PluginError),
 }
 
 #[cfg(test)]
@@ -189,32 +933,60 @@ pub mod test {
     use super::*;
 
     use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
 
     use thiserror::Error;
 
-    use crate::image::ImageName;
+    use super::{RegistryTagFetcher, TagFetcher};
+    use crate::image::{Digest, ImageName};
 
     #[derive(Debug, PartialEq, Default)]
     pub struct ArrayFetcher {
-        content: HashMap<ImageName, Vec<Tag>>,
+        content: HashMap<ImageName, Vec<TagRecord>>,
+        /// The digests that are resolved on request, but not listed with the tags.
+        digests: HashMap<(ImageName, Tag), Digest>,
+        /// Whether the tags are listed in no particular order, like registries without push dates do.
+        unordered: bool,
     }
 
     impl ArrayFetcher {
         pub fn new() -> Self {
             ArrayFetcher {
                 content: HashMap::new(),
+                digests: HashMap::new(),
+                unordered: false,
             }
         }
 
         pub fn with(image_name: ImageName, tags: Vec<Tag>) -> ArrayFetcher {
-            let mut content = HashMap::new();
-            content.insert(image_name, tags);
-            ArrayFetcher { content }
+            let mut fetcher = Self::new();
+            fetcher.insert(image_name, tags);
+            fetcher
+        }
+
+        pub fn insert(&mut self, image_name: ImageName, tags: Vec<Tag>) {
+            self.insert_records(image_name, tags.into_iter().map(TagRecord::from).collect());
+        }
+
+        pub fn insert_records(&mut self, image_name: ImageName, records: Vec<TagRecord>) {
+            self.content.insert(image_name, records);
+        }
+
+        pub fn insert_digest(&mut self, image_name: ImageName, tag: &str, digest: &str) {
+            self.digests
+                .insert((image_name, tag.to_string()), digest.to_string());
+        }
+
+        pub fn unordered(mut self) -> Self {
+            self.unordered = true;
+            self
         }
     }
 
     impl TagFetcher for ArrayFetcher {
-        type TagIter = Vec<Result<Tag, Self::FetchError>>;
+        type TagIter = Vec<Result<TagRecord, Self::FetchError>>;
         type FetchError = FetchError;
 
         fn fetch(&self, image: &ImageName) -> Self::TagIter {
@@ -227,6 +999,49 @@ pub mod test {
                     })]
                 })
         }
+
+        /// Filters like DockerHub does, so that searches are tested with filtered tags.
+        fn fetch_containing(&self, image: &ImageName, substring: &str) -> Self::TagIter {
+            self.fetch(image)
+                .into_iter()
+                .filter(|result| match result {
+                    Ok(tag) => tag.name.contains(substring),
+                    Err(_) => true,
+                })
+                .collect()
+        }
+
+        fn lists_newest_first(&self, _image: &ImageName) -> bool {
+            !self.unordered
+        }
+
+        fn digest(&self, image: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+            Ok(self.digests.get(&(image.clone(), tag.to_string())).cloned())
+        }
+    }
+
+    /// Streams the same tags, so that the async search is tested with the same fetcher.
+    #[cfg(feature = "async")]
+    impl asynchronous::AsyncTagFetcher for ArrayFetcher {
+        type TagStream =
+            futures_util::stream::Iter<std::vec::IntoIter<Result<TagRecord, FetchError>>>;
+        type FetchError = FetchError;
+
+        fn fetch(&self, image: &ImageName) -> Self::TagStream {
+            futures_util::stream::iter(TagFetcher::fetch(self, image))
+        }
+
+        fn fetch_containing(&self, image: &ImageName, substring: &str) -> Self::TagStream {
+            futures_util::stream::iter(TagFetcher::fetch_containing(self, image, substring))
+        }
+
+        fn digest<'a>(
+            &'a self,
+            image: &'a ImageName,
+            tag: &'a str,
+        ) -> futures_util::future::BoxFuture<'a, Result<Option<Digest>, Self::FetchError>> {
+            Box::pin(async move { TagFetcher::digest(self, image, tag) })
+        }
     }
 
     #[derive(Error, Debug, PartialEq)]
@@ -240,4 +1055,374 @@ pub mod test {
             FetchError { image_name }
         }
     }
+
+    #[test]
+    fn routes_to_image_registry() {
+        let registry = StandIn::start(|request| match request.path.as_str() {
+            "/v2/team/app/tags/list?n=100" => Response::ok(r#"{"name":"team/app","tags":["1.0"]}"#),
+            _ => Response::status(404),
+        });
+        let image = ImageName::parse(&format!(
+            "{}/team/app",
+            registry.url().trim_start_matches("http://")
+        ))
+        .unwrap();
+
+        let fetcher = RegistryTagFetcher::new();
+        let tags = fetcher
+            .fetch(&image)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["1.0"]);
+    }
+
+    const FIRST_PAGE: &str =
+        "/v2/repositories/library/ubuntu/tags/?page_size=100&page=1&ordering=last_updated";
+
+    #[test]
+    fn fetches_from_base_url() {
+        let docker_hub = StandIn::start(|request| match request.path.as_str() {
+            FIRST_PAGE => Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#),
+            _ => Response::status(404),
+        });
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let fetcher = DockerHubTagFetcher::new().with_base_url(docker_hub.url());
+        let tags = fetcher
+            .fetch(&ubuntu)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["20.04"]);
+        assert_eq!(docker_hub.requests().len(), 1);
+    }
+
+    #[test]
+    fn fetches_anonymously_if_credential_helper_fails() {
+        let docker_hub = StandIn::start(|request| match request.path.as_str() {
+            FIRST_PAGE => Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#),
+            _ => Response::status(404),
+        });
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+        let config = DockerConfig::parse(r#"{"credsStore": "uptag-missing"}"#).unwrap();
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_base_url(docker_hub.url())
+            .with_docker_config(Arc::new(config));
+        let tags = fetcher
+            .fetch(&ubuntu)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["20.04"]);
+    }
+
+    #[test]
+    fn reads_tag_metadata() {
+        let docker_hub = StandIn::start(|request| match request.path.as_str() {
+            FIRST_PAGE => Response::ok(
+                r#"{"results":[{
+                    "name":"20.04",
+                    "last_updated":"2021-02-04T18:32:55.123456Z",
+                    "digest":"sha256:3c6cfb5eae1f9c9d4c0ab3d1be3b0e5b8e6f4ab3d2f1c0b9a8f7e6d5c4b3a291",
+                    "full_size":28565672,
+                    "images":[
+                        {"architecture":"amd64","os":"linux","variant":null},
+                        {"architecture":"arm64","os":"linux","variant":"v8"}
+                    ]
+                }],"next":null}"#,
+            ),
+            _ => Response::status(404),
+        });
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let fetcher = DockerHubTagFetcher::new().with_base_url(docker_hub.url());
+        let record = fetcher.fetch(&ubuntu).next().unwrap().unwrap();
+
+        assert_eq!(record.size, Some(28565672));
+        assert_eq!(
+            record.describe(),
+            Some("2021-02-04, sha256:3c6cfb5eae1f, linux/amd64, linux/arm64/v8".to_string())
+        );
+    }
+
+    #[test]
+    fn describes_records_without_metadata() {
+        assert_eq!(TagRecord::from("20.04").describe(), None);
+    }
+
+    #[test]
+    fn describes_non_ascii_digests() {
+        let record = TagRecord {
+            digest: Some("sha256:3c6cfb5eae1éééé".to_string()),
+            ..TagRecord::from("20.04")
+        };
+        assert_eq!(record.describe(), Some("sha256:3c6cfb5eae1é".to_string()));
+    }
+
+    #[test]
+    fn resolves_digest_of_single_tag() {
+        let docker_hub = StandIn::start(|request| match request.path.as_str() {
+            "/v2/repositories/library/ubuntu/tags/20.04" => {
+                Response::ok(r#"{"name":"20.04","digest":"sha256:3c6cfb5eae1f"}"#)
+            }
+            _ => Response::status(404),
+        });
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let fetcher = DockerHubTagFetcher::new().with_base_url(docker_hub.url());
+        let digest = fetcher.digest(&ubuntu, "20.04").unwrap();
+
+        assert_eq!(digest, Some("sha256:3c6cfb5eae1f".to_string()));
+    }
+
+    #[test]
+    fn filters_tags_by_name() {
+        let docker_hub = StandIn::start(|request| {
+            match request.path.as_str() {
+            "/v2/repositories/library/node/tags/?page_size=50&page=1&ordering=last_updated&name=-alpine" => {
+                Response::ok(r#"{"results":[{"name":"14.5.0-alpine"}],"next":null}"#)
+            }
+            _ => Response::status(404),
+        }
+        });
+        let node = ImageName::new(None, "node".to_string());
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_page_size(50)
+            .with_base_url(docker_hub.url());
+        let tags = fetcher
+            .fetch_containing(&node, "-alpine")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["14.5.0-alpine"]);
+    }
+
+    #[test]
+    fn accepts_empty_filtered_tags() {
+        let docker_hub = StandIn::start(|_| Response::ok(r#"{"results":[],"next":null}"#));
+        let node = ImageName::new(None, "node".to_string());
+
+        let fetcher = DockerHubTagFetcher::new().with_base_url(docker_hub.url());
+        let tags = fetcher
+            .fetch_containing(&node, "-alpine")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_next_mirror() {
+        let broken_mirror = StandIn::start(|_| Response::status(502));
+        let mirror = StandIn::start(|request| match request.path.as_str() {
+            FIRST_PAGE => Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#),
+            _ => Response::status(404),
+        });
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_retry_policy(RetryPolicy::never())
+            .with_mirrors(vec![broken_mirror.url(), mirror.url()]);
+        let tags = fetcher
+            .fetch(&ubuntu)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["20.04"]);
+        assert_eq!(broken_mirror.requests().len(), 1);
+    }
+
+    #[test]
+    fn logs_in_to_the_mirror_it_falls_back_to() {
+        let broken_mirror = StandIn::start(|_| Response::status(502));
+        let mirror = StandIn::start(|request| match request.path.as_str() {
+            "/v2/users/login/" => Response::ok(r#"{"token":"mirror-token"}"#),
+            FIRST_PAGE => match request.headers.get("authorization").map(String::as_str) {
+                Some("Bearer mirror-token") => {
+                    Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#)
+                }
+                _ => Response::status(401),
+            },
+            _ => Response::status(404),
+        });
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_retry_policy(RetryPolicy::never())
+            .with_credentials(Credentials {
+                username: "user".to_string(),
+                password: "secret".to_string(),
+            })
+            .with_mirrors(vec![broken_mirror.url(), mirror.url()]);
+        let tags = fetcher
+            .fetch(&ubuntu)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["20.04"]);
+        assert_eq!(broken_mirror.requests().len(), 1);
+    }
+
+    #[test]
+    fn does_not_fall_back_on_missing_image() {
+        let first_mirror = StandIn::start(|_| Response::status(404));
+        let second_mirror = StandIn::start(|_| Response::status(502));
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_retry_policy(RetryPolicy::never())
+            .with_mirrors(vec![first_mirror.url(), second_mirror.url()]);
+        let result = fetcher.fetch(&ubuntu).next().unwrap();
+
+        assert!(matches!(result, Err(DockerHubTagFetcherError::NotFound(_))));
+        assert!(second_mirror.requests().is_empty());
+    }
+
+    #[test]
+    fn resolves_next_page_against_current_mirror() {
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+        let next = CurrentPage::next("http://mirror", "http://mirror/v2/page2".to_string());
+        assert_eq!(
+            next.get_url("http://other-mirror", &ubuntu),
+            Some("http://other-mirror/v2/page2".to_string())
+        );
+
+        let foreign = CurrentPage::next("http://mirror", "https://elsewhere/v2/page2".to_string());
+        assert_eq!(
+            foreign.get_url("http://mirror", &ubuntu),
+            Some("https://elsewhere/v2/page2".to_string())
+        );
+    }
+
+    /// A minimal HTTP server that stands in for a registry.
+    ///
+    /// Every connection is answered by the handler and then closed.
+    pub struct StandIn {
+        address: std::net::SocketAddr,
+        requests: Arc<Mutex<Vec<Request>>>,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Request {
+        pub method: String,
+        pub path: String,
+        /// The header names are lowercased.
+        pub headers: HashMap<String, String>,
+        pub body: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Response {
+        pub status: u16,
+        pub headers: Vec<(String, String)>,
+        pub body: String,
+    }
+
+    impl Response {
+        pub fn ok(body: impl Into<String>) -> Self {
+            Response {
+                status: 200,
+                headers: vec![],
+                body: body.into(),
+            }
+        }
+
+        pub fn status(status: u16) -> Self {
+            Response {
+                status,
+                headers: vec![],
+                body: String::new(),
+            }
+        }
+
+        pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+            self.headers.push((name.to_string(), value.into()));
+            self
+        }
+    }
+
+    impl StandIn {
+        pub fn start<F>(handler: F) -> Self
+        where
+            F: 'static + Fn(&Request) -> Response + Send,
+        {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = Arc::clone(&requests);
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    if let Some(request) = Self::read_request(&mut stream) {
+                        let response = handler(&request);
+                        recorded.lock().unwrap().push(request);
+                        Self::write_response(&mut stream, &response);
+                    }
+                }
+            });
+            StandIn { address, requests }
+        }
+
+        pub fn url(&self) -> String {
+            format!("http://{}", self.address)
+        }
+
+        pub fn requests(&self) -> Vec<Request> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        fn read_request(stream: &mut TcpStream) -> Option<Request> {
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).ok()?;
+            let mut request_parts = request_line.split_whitespace();
+            let method = request_parts.next()?.to_string();
+            let path = request_parts.next()?.to_string();
+
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).ok()?;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_at(line.find(':')?);
+                headers.insert(name.to_lowercase(), value[1..].trim().to_string());
+            }
+
+            let length = headers
+                .get("content-length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).ok()?;
+
+            Some(Request {
+                method,
+                path,
+                headers,
+                body: String::from_utf8_lossy(&body).to_string(),
+            })
+        }
+
+        fn write_response(stream: &mut TcpStream, response: &Response) {
+            let mut output = format!(
+                "HTTP/1.1 {} Stand-In\r\nContent-Length: {}\r\nConnection: close\r\n",
+                response.status,
+                response.body.len()
+            );
+            for (name, value) in &response.headers {
+                output.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            output.push_str("\r\n");
+            output.push_str(&response.body);
+            let _ = stream.write_all(output.as_bytes());
+        }
+    }
 }
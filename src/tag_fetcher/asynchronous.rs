@@ -0,0 +1,373 @@
+//! Fetching tags without blocking, e.g. within a tokio runtime.
+//!
+//! Only available with the `async` feature.
+
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+
+use std::sync::Arc;
+
+use super::{
+    name_filter, CredentialSource, DockerHubTagFetcher, DockerHubTagFetcherError, Login,
+    LoginResponse, Pages, Response, TagInfo, TagRecord,
+};
+use crate::credentials::Credentials;
+use crate::image::{Digest, ImageName, DOCKER_HUB};
+
+/// Enables fetching of tags belonging to an image without blocking.
+///
+/// This is the async counterpart of `TagFetcher`, see `crate::find_update_async`.
+pub trait AsyncTagFetcher {
+    type TagStream: Stream<Item = Result<TagRecord, Self::FetchError>> + Unpin;
+    type FetchError: std::error::Error + 'static;
+
+    /// Constructs a fallible stream over the `image`'s tags ordered
+    /// from newest to oldest, like `TagFetcher::fetch`.
+    ///
+    /// # Errors
+    /// If the `AsyncTagFetcher` encounters an error, it will emit an error variant
+    /// as the next stream item.
+    fn fetch(&self, image: &ImageName) -> Self::TagStream;
+
+    /// Like `fetch`, but allows the `AsyncTagFetcher` to skip tags that do not contain `substring`.
+    ///
+    /// See `TagFetcher::fetch_containing`.
+    fn fetch_containing(&self, image: &ImageName, _substring: &str) -> Self::TagStream {
+        self.fetch(image)
+    }
+
+    /// Looks up the digest of the manifest that the `image`'s `tag` currently points to.
+    ///
+    /// Resolves to `None` if the `AsyncTagFetcher` cannot resolve digests.
+    fn digest<'a>(
+        &'a self,
+        _image: &'a ImageName,
+        _tag: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Digest>, Self::FetchError>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+impl AsyncTagFetcher for DockerHubTagFetcher {
+    type TagStream = BoxStream<'static, Result<TagRecord, DockerHubTagFetcherError>>;
+    type FetchError = DockerHubTagFetcherError;
+
+    fn fetch(&self, name: &ImageName) -> Self::TagStream {
+        DockerHubTagStream::new(self, name, None)
+            .into_stream()
+            .take(self.search_limit)
+            .boxed()
+    }
+
+    /// Uses DockerHub's `name` filter, like `TagFetcher::fetch_containing` does.
+    fn fetch_containing(&self, name: &ImageName, substring: &str) -> Self::TagStream {
+        DockerHubTagStream::new(self, name, name_filter(substring))
+            .into_stream()
+            .take(self.search_limit)
+            .boxed()
+    }
+
+    fn digest<'a>(
+        &'a self,
+        name: &'a ImageName,
+        tag: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Digest>, Self::FetchError>> {
+        Box::pin(async move {
+            DockerHubTagStream::new(self, name, None)
+                .fetch_json::<TagInfo>(|pages| Some(pages.tag_url(tag)))
+                .await
+                .transpose()
+                .map(|info| info.and_then(|info| info.digest))
+        })
+    }
+}
+
+/// Fetches the tags of an image page by page, like `DockerHubTagIterator` does.
+struct DockerHubTagStream {
+    pages: Pages,
+    client: reqwest::Client,
+}
+
+impl DockerHubTagStream {
+    fn new(
+        fetcher: &DockerHubTagFetcher,
+        image_name: &ImageName,
+        name_filter: Option<String>,
+    ) -> Self {
+        DockerHubTagStream {
+            pages: Pages::new(fetcher, image_name, name_filter),
+            client: fetcher.async_client.clone(),
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = Result<TagRecord, DockerHubTagFetcherError>> {
+        stream::unfold(self, |mut tags| async move {
+            let tag = tags.next_tag().await?;
+            Some((tag, tags))
+        })
+    }
+
+    async fn next_tag(&mut self) -> Option<Result<TagRecord, DockerHubTagFetcherError>> {
+        if let Some(tag) = self.pages.fetched.pop_front() {
+            return Some(Ok(tag));
+        }
+        self.fetch_json::<Response>(Pages::page_url)
+            .await?
+            .and_then(|page| self.pages.receive(page))
+            .transpose()
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response, DockerHubTagFetcherError> {
+        let pages = &self.pages;
+        let token = pages
+            .login
+            .token_async(&self.client, pages.base_url())
+            .await?;
+        pages
+            .quota
+            .acquire_async(&pages.retry_policy)
+            .await
+            .map_err(|exhausted| DockerHubTagFetcherError::QuotaExhausted {
+                reset_in: exhausted.reset_in,
+            })?;
+        let response = pages
+            .retry_policy
+            .send_async(|| {
+                let request = self.client.get(url);
+                match &token {
+                    Some(token) => request.bearer_auth(token),
+                    None => request,
+                }
+            })
+            .await?;
+        pages.quota.update(response.headers());
+        pages.check_status(response.status(), response.headers())?;
+        Ok(response)
+    }
+
+    /// Fetches the URL that `url_for` builds for the current mirror, falling back to the next mirror if a mirror fails.
+    async fn fetch_json<R>(
+        &mut self,
+        url_for: impl Fn(&Pages) -> Option<String>,
+    ) -> Option<Result<R, DockerHubTagFetcherError>>
+    where
+        R: serde::de::DeserializeOwned,
+    {
+        loop {
+            let url = url_for(&self.pages)?;
+
+            log::info!(
+                "Fetching tags for {image}:\n{url}",
+                image = self.pages.image_name,
+                url = url
+            );
+            let result = match self.get(&url).await {
+                Ok(response) => {
+                    log::debug!("Received response with status `{}`.", response.status());
+                    log::debug!("Reading JSON body...");
+                    response.json::<R>().await.map_err(Into::into)
+                }
+                Err(error) => Err(error),
+            };
+            match result {
+                Err(error) if self.pages.fall_back(&error) => {}
+                result => return Some(result),
+            }
+        }
+    }
+}
+
+impl Login {
+    /// Like `token`, but logs in without blocking.
+    ///
+    /// Fetches that start before the first login finished log in as well.
+    async fn token_async(
+        &self,
+        client: &reqwest::Client,
+        base_url: &str,
+    ) -> Result<Option<String>, DockerHubTagFetcherError> {
+        let known = self.tokens.lock().unwrap().get(base_url).cloned();
+        if let Some(token) = known {
+            return Ok(token);
+        }
+        let token = match self.credentials_async().await {
+            Some(credentials) => Some(Self::log_in_async(client, base_url, &credentials).await?),
+            None => None,
+        };
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(base_url.to_string(), token.clone());
+        Ok(token)
+    }
+
+    /// Like `credentials`, but runs credential helpers on tokio's blocking threads.
+    async fn credentials_async(&self) -> Option<Credentials> {
+        match &self.source {
+            CredentialSource::DockerConfig(config) => {
+                let config = Arc::clone(config);
+                tokio::task::spawn_blocking(move || config.credentials_or_anonymous(DOCKER_HUB))
+                    .await
+                    .unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()))
+            }
+            _ => self.credentials(),
+        }
+    }
+
+    async fn log_in_async(
+        client: &reqwest::Client,
+        base_url: &str,
+        credentials: &Credentials,
+    ) -> Result<String, DockerHubTagFetcherError> {
+        log::info!("Logging in to DockerHub as {}...", credentials.username);
+        let login = async {
+            client
+                .post(&format!("{}/v2/users/login/", base_url))
+                .json(&serde_json::json!({
+                    "username": credentials.username,
+                    "password": credentials.password,
+                }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<LoginResponse>()
+                .await
+        };
+        let response = login
+            .await
+            .map_err(|error| DockerHubTagFetcherError::LoginFailed(error.into()))?;
+        Ok(response.token)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    use crate::credentials::DockerConfig;
+    use crate::tag_fetcher::test::{Response, StandIn};
+    use crate::tag_fetcher::RetryPolicy;
+
+    /// Runs `future` to completion on a tokio runtime, which the async client requires.
+    pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn ubuntu() -> ImageName {
+        ImageName::new(None, "ubuntu".to_string())
+    }
+
+    #[test]
+    fn streams_all_pages() {
+        let docker_hub = StandIn::start(|request| match request.path.as_str() {
+            "/v2/repositories/library/ubuntu/tags/?page_size=2&page=1&ordering=last_updated" => {
+                Response::ok(
+                    r#"{"results":[{"name":"21.04"},{"name":"20.10"}],"next":"/v2/page2"}"#,
+                )
+            }
+            "/v2/page2" => Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#),
+            _ => Response::status(404),
+        });
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_page_size(2)
+            .with_base_url(docker_hub.url());
+        let tags = block_on(AsyncTagFetcher::fetch(&fetcher, &ubuntu()).collect::<Vec<_>>())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["21.04", "20.10", "20.04"]);
+    }
+
+    #[test]
+    fn resolves_digest_without_blocking() {
+        let docker_hub = StandIn::start(|request| match request.path.as_str() {
+            "/v2/repositories/library/ubuntu/tags/20.04" => {
+                Response::ok(r#"{"name":"20.04","digest":"sha256:3c6cfb5eae1f"}"#)
+            }
+            _ => Response::status(404),
+        });
+
+        let fetcher = DockerHubTagFetcher::new().with_base_url(docker_hub.url());
+        let digest = block_on(AsyncTagFetcher::digest(&fetcher, &ubuntu(), "20.04")).unwrap();
+
+        assert_eq!(digest, Some("sha256:3c6cfb5eae1f".to_string()));
+    }
+
+    #[test]
+    fn reports_missing_image() {
+        let docker_hub = StandIn::start(|_| Response::status(404));
+
+        let fetcher = DockerHubTagFetcher::new().with_base_url(docker_hub.url());
+        let result = block_on(AsyncTagFetcher::fetch(&fetcher, &ubuntu()).next()).unwrap();
+
+        assert!(matches!(result, Err(DockerHubTagFetcherError::NotFound(_))));
+    }
+
+    #[test]
+    fn logs_in_with_credentials_from_docker_config() {
+        let docker_hub = StandIn::start(|request| match request.path.as_str() {
+            "/v2/users/login/" => Response::ok(r#"{"token":"token"}"#),
+            "/v2/repositories/library/ubuntu/tags/?page_size=100&page=1&ordering=last_updated" => {
+                match request.headers.get("authorization").map(String::as_str) {
+                    Some("Bearer token") => {
+                        Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#)
+                    }
+                    _ => Response::status(401),
+                }
+            }
+            _ => Response::status(404),
+        });
+        let config = DockerConfig::parse(
+            r#"{"auths": {"https://index.docker.io/v1/": {"auth": "dXNlcjpzZWNyZXQ="}}}"#,
+        )
+        .unwrap();
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_docker_config(Arc::new(config))
+            .with_base_url(docker_hub.url());
+        let tags = block_on(AsyncTagFetcher::fetch(&fetcher, &ubuntu()).collect::<Vec<_>>())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["20.04"]);
+    }
+
+    #[test]
+    fn logs_in_to_the_mirror_it_falls_back_to() {
+        let broken_mirror = StandIn::start(|_| Response::status(502));
+        let mirror = StandIn::start(|request| match request.path.as_str() {
+            "/v2/users/login/" => Response::ok(r#"{"token":"mirror-token"}"#),
+            "/v2/repositories/library/ubuntu/tags/?page_size=100&page=1&ordering=last_updated" => {
+                match request.headers.get("authorization").map(String::as_str) {
+                    Some("Bearer mirror-token") => {
+                        Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#)
+                    }
+                    _ => Response::status(401),
+                }
+            }
+            _ => Response::status(404),
+        });
+
+        let fetcher = DockerHubTagFetcher::new()
+            .with_retry_policy(RetryPolicy::never())
+            .with_credentials(Credentials {
+                username: "user".to_string(),
+                password: "secret".to_string(),
+            })
+            .with_mirrors(vec![broken_mirror.url(), mirror.url()]);
+        let tags = block_on(AsyncTagFetcher::fetch(&fetcher, &ubuntu()).collect::<Vec<_>>())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(tags, vec!["20.04"]);
+        assert_eq!(broken_mirror.requests().len(), 1);
+    }
+}
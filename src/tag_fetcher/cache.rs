@@ -0,0 +1,609 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::{TagFetcher, TagRecord};
+use crate::image::{Digest, ImageName};
+
+/// Caches the tags fetched by another `TagFetcher` on disk.
+///
+/// The tags are fetched lazily and the ones that were consumed are stored
+/// for later runs once the iterator is dropped. If a later run needs more
+/// tags than were stored, the tags are fetched again, so that tags pushed in
+/// the meantime come first. Once the fetched tags reach the cached ones, those
+/// are served until more are needed. Failed fetches are not cached.
+#[derive(Debug)]
+pub struct CachingTagFetcher<T> {
+    fetcher: T,
+    cache: TagCache,
+    search_limit: usize,
+    mode: CacheMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Serves fresh entries from the cache and stores newly fetched tags.
+    Use,
+    /// Ignores existing entries, but stores newly fetched tags.
+    Refresh,
+    /// Neither reads from nor writes to the cache.
+    Bypass,
+}
+
+impl<T> CachingTagFetcher<T>
+where
+    T: TagFetcher,
+{
+    /// Wraps `fetcher`, which has to be limited to `search_limit` tags.
+    pub fn new(fetcher: T, cache: TagCache, search_limit: usize) -> Self {
+        CachingTagFetcher {
+            fetcher,
+            cache,
+            search_limit,
+            mode: CacheMode::Use,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: CacheMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+impl<T> TagFetcher for CachingTagFetcher<T>
+where
+    T: TagFetcher,
+{
+    type TagIter = CachingTagIterator<<T::TagIter as IntoIterator>::IntoIter>;
+    type FetchError = T::FetchError;
+
+    fn fetch(&self, image: &ImageName) -> Self::TagIter {
+        self.fetch_cached(Key::from(image), || self.fetcher.fetch(image))
+    }
+
+    /// Filtered tags are cached separately from the image's other tags.
+    fn fetch_containing(&self, image: &ImageName, substring: &str) -> Self::TagIter {
+        let key = Key {
+            image: image.clone(),
+            substring: Some(substring.to_string()),
+        };
+        self.fetch_cached(key, || self.fetcher.fetch_containing(image, substring))
+    }
+
+    /// Digests are not cached, since a tag can be moved to a different manifest at any time.
+    fn digest(&self, image: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        self.fetcher.digest(image, tag)
+    }
+
+    fn lists_newest_first(&self, image: &ImageName) -> bool {
+        self.fetcher.lists_newest_first(image)
+    }
+}
+
+impl<T> CachingTagFetcher<T>
+where
+    T: TagFetcher,
+{
+    fn fetch_cached(
+        &self,
+        key: Key,
+        fetch: impl FnOnce() -> T::TagIter,
+    ) -> <Self as TagFetcher>::TagIter {
+        if self.mode == CacheMode::Bypass {
+            return CachingTagIterator::bypassed(fetch().into_iter());
+        }
+
+        let cached = match self.mode {
+            CacheMode::Use => self.cache.read(&key),
+            _ => None,
+        };
+        match cached {
+            Some(entry) if entry.covers(self.search_limit) => {
+                log::info!("Using cached tags for {}.", key);
+                let tags = entry.tags.into_iter().take(self.search_limit).collect();
+                CachingTagIterator::cached(tags)
+            }
+            Some(entry) => {
+                log::info!(
+                    "Fetching tags for {} again, {} of them are cached.",
+                    key,
+                    entry.tags.len()
+                );
+                let fetched = fetch().into_iter();
+                let recorder = Recorder::new(self.cache.clone(), key, self.search_limit);
+                CachingTagIterator::resumed(entry.tags, fetched, recorder)
+            }
+            None => {
+                let fetched = fetch().into_iter();
+                let recorder = Recorder::new(self.cache.clone(), key, self.search_limit);
+                CachingTagIterator::resumed(Vec::new(), fetched, recorder)
+            }
+        }
+    }
+}
+
+/// Identifies an entry: the tags of an image, or only those containing a substring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Key {
+    image: ImageName,
+    substring: Option<String>,
+}
+
+impl From<&ImageName> for Key {
+    fn from(image: &ImageName) -> Self {
+        Key {
+            image: image.clone(),
+            substring: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.substring {
+            Some(substring) => write!(f, "{} (containing `{}`)", self.image, substring),
+            None => write!(f, "{}", self.image),
+        }
+    }
+}
+
+/// Serves the fetched tags, switching to the cached tags once a fetched tag was cached, and skipping tags already served.
+pub struct CachingTagIterator<I> {
+    cached: std::vec::IntoIter<TagRecord>,
+    /// The tags of a partial entry, until a fetched tag is found among them.
+    unreached: Vec<TagRecord>,
+    fetched: Option<I>,
+    /// Records the served tags, unless the cache is bypassed or the cached tags suffice.
+    recorder: Option<Recorder>,
+}
+
+impl<I> CachingTagIterator<I> {
+    fn cached(tags: Vec<TagRecord>) -> Self {
+        CachingTagIterator {
+            cached: tags.into_iter(),
+            unreached: Vec::new(),
+            fetched: None,
+            recorder: None,
+        }
+    }
+
+    fn bypassed(fetched: I) -> Self {
+        CachingTagIterator {
+            cached: Vec::new().into_iter(),
+            unreached: Vec::new(),
+            fetched: Some(fetched),
+            recorder: None,
+        }
+    }
+
+    fn resumed(cached: Vec<TagRecord>, fetched: I, recorder: Recorder) -> Self {
+        CachingTagIterator {
+            cached: Vec::new().into_iter(),
+            unreached: cached,
+            fetched: Some(fetched),
+            recorder: Some(recorder),
+        }
+    }
+}
+
+impl<I, E> Iterator for CachingTagIterator<I>
+where
+    I: Iterator<Item = Result<TagRecord, E>>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(recorder) = &self.recorder {
+            if recorder.tags.len() >= recorder.search_limit {
+                return None;
+            }
+        }
+
+        let (next, fetched) = match self.cached.next() {
+            Some(tag) => (Some(Ok(tag)), false),
+            None => loop {
+                let next = self.fetched.as_mut()?.next();
+                match (&next, &self.recorder) {
+                    // The cached tags shift the fetched ones once they run out, so some were already served.
+                    (Some(Ok(tag)), Some(recorder)) if recorder.has_served(tag) => continue,
+                    (Some(Ok(tag)), _) => {
+                        // The cached tags following a fetched one are served without fetching them again.
+                        if let Some(position) = self
+                            .unreached
+                            .iter()
+                            .position(|cached| cached.name == tag.name)
+                        {
+                            self.cached = self.unreached.split_off(position + 1).into_iter();
+                            self.unreached.clear();
+                        }
+                        break (next, true);
+                    }
+                    _ => break (next, true),
+                }
+            },
+        };
+
+        if let Some(recorder) = &mut self.recorder {
+            match &next {
+                Some(Ok(tag)) => recorder.record(tag, fetched),
+                Some(Err(_)) => recorder.failed = true,
+                None => recorder.exhausted = true,
+            }
+        }
+        next
+    }
+}
+
+/// Stores the served tags in the cache once the iterator is dropped.
+struct Recorder {
+    cache: TagCache,
+    key: Key,
+    search_limit: usize,
+    tags: Vec<TagRecord>,
+    names: HashSet<String>,
+    /// Whether any tags were fetched, as opposed to served from the cache.
+    fetched: bool,
+    /// Whether all tags were served, so that the entry covers every search limit.
+    exhausted: bool,
+    failed: bool,
+}
+
+impl Recorder {
+    fn new(cache: TagCache, key: Key, search_limit: usize) -> Self {
+        Recorder {
+            cache,
+            key,
+            search_limit,
+            tags: Vec::new(),
+            names: HashSet::new(),
+            fetched: false,
+            exhausted: false,
+            failed: false,
+        }
+    }
+
+    fn record(&mut self, tag: &TagRecord, fetched: bool) {
+        self.fetched |= fetched;
+        self.names.insert(tag.name.clone());
+        self.tags.push(tag.clone());
+    }
+
+    fn has_served(&self, tag: &TagRecord) -> bool {
+        self.names.contains(&tag.name)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Rewriting an entry without fetching would extend the lifetime of the cached tags.
+        if self.failed || !self.fetched {
+            return;
+        }
+        let covered = if self.exhausted {
+            self.search_limit
+        } else {
+            self.tags.len()
+        };
+        if let Err(error) = self.cache.write(&self.key, &self.tags, covered) {
+            log::warn!("Failed to cache tags for {}: {}", self.key, error);
+        }
+    }
+}
+
+/// A directory of tag lists, one file per image and filter.
+///
+/// Entries are replaced atomically, so multiple processes can share a cache directory.
+#[derive(Debug, Clone)]
+pub struct TagCache {
+    directory: PathBuf,
+    ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    /// How many tags were requested, which is more than were stored if the image has fewer tags.
+    search_limit: usize,
+    tags: Vec<TagRecord>,
+}
+
+impl Entry {
+    /// Whether the entry contains the first `search_limit` tags, or all tags if there are fewer.
+    fn covers(&self, search_limit: usize) -> bool {
+        let exhausted = self.tags.len() < self.search_limit;
+        self.search_limit >= search_limit || exhausted
+    }
+}
+
+impl TagCache {
+    /// Uses `directory` for the entries, treating entries older than `ttl` as stale.
+    pub fn new(directory: impl Into<PathBuf>, ttl: Duration) -> Self {
+        TagCache {
+            directory: directory.into(),
+            ttl,
+        }
+    }
+
+    /// The platform's cache directory, e.g. `$XDG_CACHE_HOME/uptag/tags` or `~/.cache/uptag/tags` on Linux.
+    pub fn default_directory() -> Option<PathBuf> {
+        let base = if cfg!(target_os = "windows") {
+            std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Caches"))
+        } else {
+            std::env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .filter(|path| path.is_absolute())
+                .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        }?;
+        Some(base.join("uptag").join("tags"))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn entry_path(&self, key: &Key) -> PathBuf {
+        // Image names cannot contain `%`, so this encoding is unambiguous.
+        let mut file_name = key
+            .image
+            .to_string()
+            .replace('/', "%2F")
+            .replace(':', "%3A");
+        if let Some(substring) = &key.substring {
+            file_name.push_str("%3Fname=");
+            file_name.push_str(&substring.replace('%', "%25").replace('/', "%2F"));
+        }
+        self.directory.join(format!("{}.json", file_name))
+    }
+
+    /// Reads the entry of `key` if it is fresh.
+    fn read(&self, key: &Key) -> Option<Entry> {
+        let input = std::fs::read_to_string(self.entry_path(key)).ok()?;
+        let entry = match serde_json::from_str::<Entry>(&input) {
+            Ok(entry) => entry,
+            Err(error) => {
+                log::warn!("Ignoring corrupt cache entry for {}: {}", key, error);
+                return None;
+            }
+        };
+
+        let age = now().saturating_sub(entry.fetched_at);
+        if age > self.ttl.as_secs() {
+            return None;
+        }
+
+        Some(entry)
+    }
+
+    fn write(&self, key: &Key, tags: &[TagRecord], search_limit: usize) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        let entry = Entry {
+            fetched_at: now(),
+            search_limit,
+            tags: tags.to_vec(),
+        };
+        let output = serde_json::to_string(&entry)?;
+
+        // Writing to a temporary file first ensures that readers never see a partially written entry.
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = self.entry_path(key);
+        let temporary = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::write(&temporary, output)?;
+        std::fs::rename(&temporary, &path).map_err(|error| {
+            let _ = std::fs::remove_file(&temporary);
+            error
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+
+    use crate::tag_fetcher::test::{ArrayFetcher, FetchError};
+
+    /// Counts how often tags were fetched.
+    struct CountingFetcher {
+        fetcher: ArrayFetcher,
+        count: Cell<usize>,
+    }
+
+    impl TagFetcher for CountingFetcher {
+        type TagIter = <ArrayFetcher as TagFetcher>::TagIter;
+        type FetchError = FetchError;
+
+        fn fetch(&self, image: &ImageName) -> Self::TagIter {
+            self.count.set(self.count.get() + 1);
+            self.fetcher.fetch(image)
+        }
+    }
+
+    fn ubuntu() -> ImageName {
+        ImageName::new(None, "ubuntu".to_string())
+    }
+
+    fn counting_fetcher() -> CountingFetcher {
+        CountingFetcher {
+            fetcher: ArrayFetcher::with(ubuntu(), vec!["14.05".to_string(), "14.04".to_string()]),
+            count: Cell::new(0),
+        }
+    }
+
+    fn fetch_all<T: TagFetcher>(
+        fetcher: &T,
+        image: &ImageName,
+    ) -> Result<Vec<TagRecord>, T::FetchError> {
+        fetcher.fetch(image).into_iter().collect()
+    }
+
+    #[test]
+    fn serves_fresh_entries_from_cache() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        let fetcher = CachingTagFetcher::new(counting_fetcher(), cache, 100);
+
+        let first = fetch_all(&fetcher, &ubuntu()).unwrap();
+        let second = fetch_all(&fetcher, &ubuntu()).unwrap();
+
+        assert_eq!(first, vec!["14.05", "14.04"]);
+        assert_eq!(second, first);
+        assert_eq!(fetcher.fetcher.count.get(), 1);
+    }
+
+    #[test]
+    fn refetches_stale_entries() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        cache
+            .write(&Key::from(&ubuntu()), &["14.04".into()], 100)
+            .unwrap();
+        let entry_path = cache.entry_path(&Key::from(&ubuntu()));
+        let mut entry: Entry =
+            serde_json::from_str(&std::fs::read_to_string(&entry_path).unwrap()).unwrap();
+        entry.fetched_at -= 61;
+        std::fs::write(&entry_path, serde_json::to_string(&entry).unwrap()).unwrap();
+
+        let fetcher = CachingTagFetcher::new(counting_fetcher(), cache, 100);
+        let tags = fetch_all(&fetcher, &ubuntu()).unwrap();
+
+        assert_eq!(tags, vec!["14.05", "14.04"]);
+        assert_eq!(fetcher.fetcher.count.get(), 1);
+    }
+
+    #[test]
+    fn refetches_entries_with_smaller_search_limit() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        cache
+            .write(&Key::from(&ubuntu()), &["14.05".into()], 1)
+            .unwrap();
+
+        let fetcher = CachingTagFetcher::new(counting_fetcher(), cache, 100);
+        let tags = fetch_all(&fetcher, &ubuntu()).unwrap();
+
+        assert_eq!(tags, vec!["14.05", "14.04"]);
+        assert_eq!(fetcher.fetcher.count.get(), 1);
+    }
+
+    #[test]
+    fn stores_only_consumed_tags() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        let fetcher = CachingTagFetcher::new(counting_fetcher(), cache.clone(), 100);
+
+        let first = fetcher.fetch(&ubuntu()).next().unwrap().unwrap();
+        assert_eq!(first, "14.05");
+
+        let entry = cache.read(&Key::from(&ubuntu())).unwrap();
+        assert_eq!(entry.tags, vec!["14.05"]);
+        assert!(!entry.covers(100));
+    }
+
+    #[test]
+    fn resumes_partial_entries() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        // The entry is outdated, since `14.06` was pushed in the meantime.
+        cache
+            .write(&Key::from(&ubuntu()), &["14.04".into()], 1)
+            .unwrap();
+        let fetcher = CachingTagFetcher::new(
+            ArrayFetcher::with(
+                ubuntu(),
+                vec![
+                    "14.06".to_string(),
+                    "14.04".to_string(),
+                    "14.03".to_string(),
+                ],
+            ),
+            cache.clone(),
+            100,
+        );
+
+        let tags = fetch_all(&fetcher, &ubuntu()).unwrap();
+
+        assert_eq!(tags, vec!["14.06", "14.04", "14.03"]);
+        assert!(cache.read(&Key::from(&ubuntu())).unwrap().covers(100));
+    }
+
+    #[test]
+    fn caches_filtered_tags_separately() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        let fetcher = CachingTagFetcher::new(counting_fetcher(), cache.clone(), 100);
+
+        fetcher
+            .fetch_containing(&ubuntu(), ".05")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        fetch_all(&fetcher, &ubuntu()).unwrap();
+
+        assert_eq!(fetcher.fetcher.count.get(), 2);
+        let filtered = Key {
+            image: ubuntu(),
+            substring: Some(".05".to_string()),
+        };
+        assert!(cache.read(&filtered).is_some());
+        assert!(cache.read(&Key::from(&ubuntu())).is_some());
+    }
+
+    #[test]
+    fn refreshes_entries() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        cache
+            .write(&Key::from(&ubuntu()), &["14.04".into()], 100)
+            .unwrap();
+
+        let fetcher = CachingTagFetcher::new(counting_fetcher(), cache.clone(), 100)
+            .with_mode(CacheMode::Refresh);
+        fetch_all(&fetcher, &ubuntu()).unwrap();
+
+        assert_eq!(fetcher.fetcher.count.get(), 1);
+        assert_eq!(
+            cache.read(&Key::from(&ubuntu())).map(|entry| entry.tags),
+            Some(vec!["14.05".into(), "14.04".into()])
+        );
+    }
+
+    #[test]
+    fn bypasses_cache() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        let fetcher = CachingTagFetcher::new(counting_fetcher(), cache.clone(), 100)
+            .with_mode(CacheMode::Bypass);
+
+        fetch_all(&fetcher, &ubuntu()).unwrap();
+
+        assert!(cache.read(&Key::from(&ubuntu())).is_none());
+    }
+
+    #[test]
+    fn does_not_cache_failures() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = TagCache::new(directory.path(), Duration::from_secs(60));
+        let fetcher = CachingTagFetcher::new(ArrayFetcher::new(), cache.clone(), 100);
+
+        let missing = ImageName::new(None, "missing".to_string());
+        assert!(fetch_all(&fetcher, &missing).is_err());
+        assert!(cache.read(&Key::from(&missing)).is_none());
+    }
+}
@@ -0,0 +1,593 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::{Certificate, Proxy, Url};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Identifies uptag to registries, e.g. `uptag/1.0.1 (+https://github.com/Y0hy0h/uptag)`.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "uptag/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/Y0hy0h/uptag)"
+);
+
+/// Configures the HTTP client that the tag fetchers share, see `build_client`.
+///
+/// Without an explicit proxy, the proxies given in `HTTP_PROXY`, `HTTPS_PROXY`
+/// and `NO_PROXY` are used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpConfig {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxy: Option<String>,
+    no_proxy: Vec<String>,
+    ca_certificates: Vec<PathBuf>,
+    user_agent: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+            proxy: None,
+            no_proxy: Vec::new(),
+            ca_certificates: Vec::new(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Limits how long establishing a connection may take, or never gives up if `None`.
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Limits how long a request may take until its response was read, or never gives up if `None`.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends all requests through the proxy at `url`, e.g. `http://proxy.example.com:3128`.
+    pub fn with_proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// Bypasses the proxy given via `with_proxy` for `hosts`.
+    ///
+    /// A host also matches its subdomains, and `*` matches every host, like in `NO_PROXY`.
+    pub fn with_no_proxy(mut self, hosts: impl IntoIterator<Item = String>) -> Self {
+        self.no_proxy.extend(hosts);
+        self
+    }
+
+    /// Trusts the certificates in the PEM file at `path` in addition to the system's, e.g. to pass a TLS-intercepting proxy.
+    pub fn with_ca_certificate(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_certificates.push(path.into());
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Builds a client with this configuration.
+    ///
+    /// Clones of the client share their connection pool, so pass clones to all fetchers.
+    pub fn build_client(&self) -> Result<Client, HttpError> {
+        let mut builder = Client::builder()
+            .user_agent(self.user_agent.as_str())
+            .timeout(self.timeout);
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if let Some(proxy) = self.build_proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in self.certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+
+        builder.build().map_err(HttpError::Build)
+    }
+
+    /// Builds an async client with this configuration, as used by `AsyncTagFetcher`s.
+    ///
+    /// Requests sent with it are neither recorded nor replayed.
+    #[cfg(feature = "async")]
+    pub fn build_async_client(&self) -> Result<reqwest::Client, HttpError> {
+        let mut builder = reqwest::Client::builder().user_agent(self.user_agent.as_str());
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if let Some(proxy) = self.build_proxy()? {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in self.certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+
+        builder.build().map_err(HttpError::Build)
+    }
+
+    fn build_proxy(&self) -> Result<Option<Proxy>, HttpError> {
+        let proxy = match &self.proxy {
+            Some(proxy) => proxy,
+            None => return Ok(None),
+        };
+        let url = Url::parse(proxy).map_err(|_| HttpError::InvalidProxy(proxy.clone()))?;
+        let no_proxy = self.no_proxy.clone();
+        Ok(Some(Proxy::custom(move |target| match target.host_str() {
+            Some(host) if bypasses_proxy(&no_proxy, host) => None,
+            _ => Some(url.clone()),
+        })))
+    }
+
+    fn certificates(&self) -> Result<Vec<Certificate>, HttpError> {
+        let mut certificates = Vec::new();
+        for path in &self.ca_certificates {
+            certificates.extend(read_certificates(path)?);
+        }
+        Ok(certificates)
+    }
+}
+
+/// A client with the default configuration, as used by fetchers that were not given a client.
+pub(crate) fn default_client() -> Client {
+    HttpConfig::default()
+        .build_client()
+        .expect("The default HTTP configuration is valid.")
+}
+
+/// An async client with the default configuration, see `default_client`.
+#[cfg(feature = "async")]
+pub(crate) fn default_async_client() -> reqwest::Client {
+    HttpConfig::default()
+        .build_async_client()
+        .expect("The default HTTP configuration is valid.")
+}
+
+/// Sends the requests of the tag fetchers, optionally recording them to or replaying them from a `Cassette`.
+///
+/// Clones share the connection pool and the cassette.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    tape: Option<Arc<Tape>>,
+}
+
+#[derive(Debug)]
+enum Tape {
+    /// Saves the cassette to `path` once the last clone of the client is dropped.
+    Recording {
+        path: PathBuf,
+        cassette: Mutex<Cassette>,
+    },
+    /// The recorded responses by method and URL, in the order they were recorded.
+    Replaying(Mutex<HashMap<(String, String), VecDeque<RecordedResponse>>>),
+}
+
+impl HttpClient {
+    /// Sends the requests with `client` and records the exchanges in a cassette at `path`.
+    ///
+    /// The cassette is saved once the last clone of the returned client is dropped.
+    pub fn recording(client: Client, path: impl Into<PathBuf>) -> Self {
+        HttpClient {
+            client,
+            tape: Some(Arc::new(Tape::Recording {
+                path: path.into(),
+                cassette: Mutex::new(Cassette::default()),
+            })),
+        }
+    }
+
+    /// Answers the requests with the responses recorded in `cassette` instead of sending them.
+    ///
+    /// Requests are matched by method and URL. A request that was sent more often
+    /// than it was recorded fails with `SendError::Unrecorded`.
+    pub fn replaying(cassette: Cassette) -> Self {
+        let mut responses = HashMap::<_, VecDeque<_>>::new();
+        for interaction in cassette.interactions {
+            let key = (interaction.request.method, interaction.request.url);
+            responses
+                .entry(key)
+                .or_default()
+                .push_back(interaction.response);
+        }
+        HttpClient {
+            client: default_client(),
+            tape: Some(Arc::new(Tape::Replaying(Mutex::new(responses)))),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn head(&self, url: &str) -> RequestBuilder {
+        self.client.head(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Sends `request`, which has to be built by this client.
+    pub fn send(&self, request: RequestBuilder) -> Result<Response, SendError> {
+        let tape = match &self.tape {
+            Some(tape) => tape,
+            None => return Ok(request.send()?),
+        };
+        let request = request.build()?;
+        let method = request.method().to_string();
+        let url = request.url().to_string();
+        match tape.as_ref() {
+            Tape::Replaying(responses) => {
+                let recorded = responses
+                    .lock()
+                    .unwrap()
+                    .get_mut(&(method.clone(), url.clone()))
+                    .and_then(VecDeque::pop_front)
+                    .ok_or(SendError::Unrecorded { method, url })?;
+                Ok(recorded.into_response())
+            }
+            Tape::Recording { cassette, .. } => {
+                let response = self.client.execute(request)?;
+                let recorded = RecordedResponse::read(response)?;
+                cassette.lock().unwrap().interactions.push(Interaction {
+                    request: RecordedRequest { method, url },
+                    response: recorded.clone(),
+                });
+                Ok(recorded.into_response())
+            }
+        }
+    }
+}
+
+impl From<Client> for HttpClient {
+    fn from(client: Client) -> Self {
+        HttpClient { client, tape: None }
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::from(default_client())
+    }
+}
+
+impl Drop for Tape {
+    fn drop(&mut self) {
+        if let Tape::Recording { path, cassette } = self {
+            let cassette = cassette.get_mut().unwrap();
+            if let Err(error) = cassette.save(path) {
+                match std::error::Error::source(&error) {
+                    Some(cause) => log::error!("{}: {}", error, cause),
+                    None => log::error!("{}", error),
+                }
+            }
+        }
+    }
+}
+
+/// The HTTP exchanges of a run, stored as JSON.
+///
+/// Credentials are not recorded: request headers and bodies are left out,
+/// and tokens in response bodies are redacted.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    /// The header names are lowercased.
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+/// Fields of response bodies that hold secrets.
+const SECRET_FIELDS: &[&str] = &["token", "access_token", "refresh_token"];
+
+impl RecordedResponse {
+    fn read(response: Response) -> Result<Self, reqwest::Error> {
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| *name != reqwest::header::SET_COOKIE)
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = Self::redact(response.text()?);
+        Ok(RecordedResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn redact(body: String) -> String {
+        let mut object = match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(object)) => object,
+            _ => return body,
+        };
+        let mut redacted = false;
+        for field in SECRET_FIELDS {
+            if let Some(value) = object.get_mut(*field) {
+                *value = serde_json::Value::from("redacted");
+                redacted = true;
+            }
+        }
+        if redacted {
+            serde_json::Value::Object(object).to_string()
+        } else {
+            body
+        }
+    }
+
+    fn into_response(self) -> Response {
+        let mut response = ::http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            response = response.header(name.as_str(), value.as_str());
+        }
+        // The status and headers were valid when they were recorded, so only a corrupt cassette fails here.
+        let response = response.body(self.body).unwrap_or_else(|_| {
+            ::http::Response::builder()
+                .status(500)
+                .body("The cassette contains an invalid response.".to_string())
+                .unwrap()
+        });
+        Response::from(response)
+    }
+}
+
+impl Cassette {
+    pub fn from_path(path: &Path) -> Result<Self, CassetteError> {
+        let input = std::fs::read_to_string(path).map_err(|error| CassetteError::Read {
+            path: path.display().to_string(),
+            source: error,
+        })?;
+        serde_json::from_str(&input).map_err(|error| CassetteError::Parse {
+            path: path.display().to_string(),
+            source: error,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CassetteError> {
+        let output = serde_json::to_string_pretty(self).unwrap(); // Serializing strings cannot fail.
+        std::fs::write(path, output).map_err(|error| CassetteError::Write {
+            path: path.display().to_string(),
+            source: error,
+        })
+    }
+}
+
+/// Whether `host` matches one of the `no_proxy` entries.
+fn bypasses_proxy(no_proxy: &[String], host: &str) -> bool {
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().trim_start_matches('.');
+        entry == "*"
+            || host.eq_ignore_ascii_case(entry)
+            || host
+                .to_ascii_lowercase()
+                .ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+    })
+}
+
+/// Reads every certificate of the PEM bundle at `path`.
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>, HttpError> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+
+    let bundle = std::fs::read_to_string(path).map_err(|error| HttpError::ReadCertificate {
+        path: path.display().to_string(),
+        source: error,
+    })?;
+    let certificates = bundle
+        .match_indices(BEGIN)
+        .map(|(start, _)| {
+            // Each certificate extends until the next one begins.
+            let end = bundle[start + BEGIN.len()..]
+                .find(BEGIN)
+                .map_or(bundle.len(), |end| start + BEGIN.len() + end);
+            Certificate::from_pem(&bundle.as_bytes()[start..end]).map_err(|error| {
+                HttpError::InvalidCertificate {
+                    path: path.display().to_string(),
+                    source: error,
+                }
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err(HttpError::NoCertificates(path.display().to_string()));
+    }
+    Ok(certificates)
+}
+
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error("Failed to read the CA certificates `{path}`")]
+    ReadCertificate {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid CA certificate in `{path}`")]
+    InvalidCertificate {
+        path: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("`{0}` does not contain any PEM-encoded certificate")]
+    NoCertificates(String),
+    #[error("Invalid proxy URL `{0}`")]
+    InvalidProxy(String),
+    #[error("Failed to set up the HTTP client")]
+    Build(#[source] reqwest::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum SendError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("The cassette contains no response to `{method} {url}`")]
+    Unrecorded { method: String, url: String },
+}
+
+impl SendError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, SendError::Request(error) if error.is_timeout())
+    }
+
+    pub fn is_connect(&self) -> bool {
+        matches!(self, SendError::Request(error) if error.is_connect())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CassetteError {
+    #[error("Failed to read cassette `{path}`")]
+    Read {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to parse cassette `{path}`")]
+    Parse {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Failed to write cassette `{path}`")]
+    Write {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::image::ImageName;
+    use crate::tag_fetcher::test::{Response, StandIn};
+    use crate::tag_fetcher::{DockerHubTagFetcher, TagFetcher};
+
+    #[test]
+    fn sends_user_agent() {
+        let server = StandIn::start(|_| Response::ok(""));
+
+        let client = HttpConfig::default()
+            .with_user_agent("uptag-test")
+            .build_client()
+            .unwrap();
+        client.get(&server.url()).send().unwrap();
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].headers.get("user-agent"),
+            Some(&"uptag-test".to_string())
+        );
+    }
+
+    #[test]
+    fn routes_requests_through_proxy() {
+        let proxy = StandIn::start(|_| Response::ok(""));
+
+        let client = HttpConfig::default()
+            .with_proxy(proxy.url())
+            .build_client()
+            .unwrap();
+        client.get("http://registry.invalid/v2/").send().unwrap();
+
+        assert_eq!(proxy.requests()[0].path, "http://registry.invalid/v2/");
+    }
+
+    #[test]
+    fn replays_recorded_responses() {
+        let docker_hub = StandIn::start(|_| {
+            Response::ok(r#"{"results":[{"name":"20.04"}],"next":null}"#)
+                .with_header("Content-Type", "application/json")
+        });
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cassette.json");
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let recording = DockerHubTagFetcher::new()
+            .with_base_url(docker_hub.url())
+            .with_client(HttpClient::recording(default_client(), &path));
+        let recorded = recording.fetch(&ubuntu).collect::<Result<Vec<_>, _>>();
+        drop(recording);
+
+        let cassette = Cassette::from_path(&path).unwrap();
+        let replaying = DockerHubTagFetcher::new()
+            .with_base_url(docker_hub.url())
+            .with_client(HttpClient::replaying(cassette));
+        let replayed = replaying.fetch(&ubuntu).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(replayed.unwrap(), recorded.unwrap());
+        assert_eq!(docker_hub.requests().len(), 1);
+    }
+
+    #[test]
+    fn fails_on_unrecorded_requests() {
+        let client = HttpClient::replaying(Cassette::default());
+        let result = client.send(client.get("http://registry.invalid/v2/"));
+        assert!(matches!(result, Err(SendError::Unrecorded { .. })));
+    }
+
+    #[test]
+    fn redacts_tokens() {
+        let body = RecordedResponse::redact(r#"{"token":"secret","expires_in":300}"#.to_string());
+        assert!(!body.contains("secret"));
+        assert!(body.contains("expires_in"));
+    }
+
+    #[test]
+    fn bypasses_proxy_for_subdomains() {
+        let no_proxy = vec![".example.com".to_string(), "localhost".to_string()];
+        assert!(bypasses_proxy(&no_proxy, "registry.example.com"));
+        assert!(bypasses_proxy(&no_proxy, "localhost"));
+        assert!(!bypasses_proxy(&no_proxy, "notexample.com"));
+        assert!(bypasses_proxy(&["*".to_string()], "ghcr.io"));
+    }
+
+    #[test]
+    fn rejects_files_without_certificates() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("empty.pem");
+        std::fs::write(&path, "").unwrap();
+
+        let result = HttpConfig::default()
+            .with_ca_certificate(&path)
+            .build_client();
+        assert!(matches!(result, Err(HttpError::NoCertificates(_))));
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::{TagFetcher, TagRecord};
+use crate::image::{Digest, ImageName};
+
+/// Fetches the tags of each image at most once, sharing them between all searches of the image.
+///
+/// This avoids fetching an image again for each service or Dockerfile that
+/// references it. The tags are fetched lazily: a search that reads past the
+/// tags fetched so far continues the shared fetch, so no more tags are
+/// fetched than the most demanding search needs, up to the search limit of
+/// the wrapped fetcher. Searches of the same image wait for each other while
+/// a tag is fetched.
+///
+/// Errors are shared as well, so that a failed image is not fetched again.
+/// The tags are kept as long as the fetcher, so use a new one for each run.
+pub struct MemoizingTagFetcher<T>
+where
+    T: TagFetcher,
+{
+    /// Shared with the memos, which start fetching once their tags are first read.
+    fetcher: Arc<T>,
+    tags: Mutex<HashMap<Key, Arc<Memo<T>>>>,
+    /// The digests by image and tag.
+    digests: Mutex<HashMap<(ImageName, String), DigestResult<T>>>,
+}
+
+/// An image and the substring its tags were filtered by, if any.
+type Key = (ImageName, Option<String>);
+
+type DigestResult<T> = Result<Option<Digest>, Arc<<T as TagFetcher>::FetchError>>;
+
+type Memo<T> = Mutex<
+    MemoState<
+        <<T as TagFetcher>::TagIter as IntoIterator>::IntoIter,
+        <T as TagFetcher>::FetchError,
+    >,
+>;
+
+impl<T> MemoizingTagFetcher<T>
+where
+    T: TagFetcher,
+{
+    pub fn new(fetcher: T) -> Self {
+        MemoizingTagFetcher {
+            fetcher: Arc::new(fetcher),
+            tags: Mutex::new(HashMap::new()),
+            digests: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> MemoizingTagFetcher<T>
+where
+    T: TagFetcher + Send + Sync + 'static,
+{
+    fn memoized(&self, key: Key) -> <Self as TagFetcher>::TagIter {
+        let mut memos = self.tags.lock().unwrap();
+        let memo = match memos.get(&key) {
+            Some(memo) => {
+                log::info!("Reusing the tags fetched for {}.", key.0);
+                Arc::clone(memo)
+            }
+            None => {
+                let fetcher = Arc::clone(&self.fetcher);
+                let (image, substring) = key.clone();
+                // The fetch starts on the first read, so that it does not block the lookups of other images.
+                let start: Start<_> = Box::new(move || {
+                    match &substring {
+                        Some(substring) => fetcher.fetch_containing(&image, substring),
+                        None => fetcher.fetch(&image),
+                    }
+                    .into_iter()
+                });
+                let memo = Arc::new(Mutex::new(MemoState {
+                    tags: Vec::new(),
+                    start: Some(start),
+                    fetched: None,
+                    error: None,
+                }));
+                memos.insert(key, Arc::clone(&memo));
+                memo
+            }
+        };
+        MemoizedTagIterator {
+            memo,
+            position: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for MemoizingTagFetcher<T>
+where
+    T: TagFetcher + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoizingTagFetcher")
+            .field("fetcher", &self.fetcher)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> TagFetcher for MemoizingTagFetcher<T>
+where
+    T: TagFetcher + Send + Sync + 'static,
+{
+    type TagIter = MemoizedTagIterator<<T::TagIter as IntoIterator>::IntoIter, T::FetchError>;
+    type FetchError = Arc<T::FetchError>;
+
+    fn fetch(&self, image: &ImageName) -> Self::TagIter {
+        self.memoized((image.clone(), None))
+    }
+
+    /// Filtered tags are memoized separately from the image's other tags.
+    fn fetch_containing(&self, image: &ImageName, substring: &str) -> Self::TagIter {
+        self.memoized((image.clone(), Some(substring.to_string())))
+    }
+
+    fn digest(&self, image: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        let key = (image.clone(), tag.to_string());
+        if let Some(digest) = self.digests.lock().unwrap().get(&key) {
+            return digest.clone();
+        }
+        // Concurrent lookups of the same digest are rare, so they are not waited for.
+        let digest = self.fetcher.digest(image, tag).map_err(Arc::new);
+        self.digests.lock().unwrap().insert(key, digest.clone());
+        digest
+    }
+
+    fn lists_newest_first(&self, image: &ImageName) -> bool {
+        self.fetcher.lists_newest_first(image)
+    }
+}
+
+/// Starts fetching the tags of an image.
+type Start<I> = Box<dyn FnOnce() -> I + Send>;
+
+/// The tags fetched so far, shared between the searches of an image.
+struct MemoState<I, E> {
+    tags: Vec<TagRecord>,
+    /// Is `None` once the fetch started.
+    start: Option<Start<I>>,
+    /// Is `None` until the fetch started, and once all tags were fetched or the fetch failed.
+    fetched: Option<I>,
+    error: Option<Arc<E>>,
+}
+
+/// Serves the memoized tags, continuing the shared fetch once they run out.
+pub struct MemoizedTagIterator<I, E> {
+    memo: Arc<Mutex<MemoState<I, E>>>,
+    /// The index of the next tag.
+    position: usize,
+    /// Whether the error was emitted, which ends the iterator.
+    failed: bool,
+}
+
+impl<I, E> Iterator for MemoizedTagIterator<I, E>
+where
+    I: Iterator<Item = Result<TagRecord, E>>,
+{
+    type Item = Result<TagRecord, Arc<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        // Holding the lock lets other searches of the image wait for the tag instead of fetching it, too.
+        let mut memo = self.memo.lock().unwrap();
+        if let Some(start) = memo.start.take() {
+            memo.fetched = Some(start());
+        }
+        while self.position >= memo.tags.len() {
+            if let Some(error) = &memo.error {
+                self.failed = true;
+                return Some(Err(Arc::clone(error)));
+            }
+            match memo.fetched.as_mut()?.next() {
+                Some(Ok(tag)) => memo.tags.push(tag),
+                Some(Err(error)) => {
+                    memo.fetched = None;
+                    memo.error = Some(Arc::new(error));
+                }
+                None => memo.fetched = None,
+            }
+        }
+        let tag = memo.tags[self.position].clone();
+        self.position += 1;
+        Some(Ok(tag))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::tag_fetcher::test::{ArrayFetcher, FetchError};
+
+    /// Counts how often the tags were fetched and how many tags were read.
+    struct CountingFetcher {
+        fetcher: ArrayFetcher,
+        fetches: AtomicUsize,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl TagFetcher for CountingFetcher {
+        type TagIter = Box<dyn Iterator<Item = Result<TagRecord, FetchError>> + Send>;
+        type FetchError = FetchError;
+
+        fn fetch(&self, image: &ImageName) -> Self::TagIter {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            let reads = Arc::clone(&self.reads);
+            Box::new(self.fetcher.fetch(image).into_iter().inspect(move |_| {
+                reads.fetch_add(1, Ordering::SeqCst);
+            }))
+        }
+    }
+
+    fn counting(image: &ImageName, tags: &[&str]) -> CountingFetcher {
+        CountingFetcher {
+            fetcher: ArrayFetcher::with(
+                image.clone(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+            ),
+            fetches: AtomicUsize::new(0),
+            reads: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn node() -> ImageName {
+        ImageName::new(None, "node".to_string())
+    }
+
+    #[test]
+    fn fetches_each_image_once() {
+        let fetcher = MemoizingTagFetcher::new(counting(&node(), &["15", "14", "13"]));
+
+        let first = fetcher
+            .fetch(&node())
+            .take(2)
+            .collect::<Result<Vec<_>, _>>();
+        let second = fetcher.fetch(&node()).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(first.unwrap(), vec!["15", "14"]);
+        assert_eq!(second.unwrap(), vec!["15", "14", "13"]);
+        assert_eq!(fetcher.fetcher.fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(fetcher.fetcher.reads.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn fetches_only_the_tags_that_are_searched() {
+        let fetcher = MemoizingTagFetcher::new(counting(&node(), &["15", "14", "13"]));
+
+        fetcher.fetch(&node()).next();
+        fetcher.fetch(&node()).next();
+
+        assert_eq!(fetcher.fetcher.reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn starts_fetching_on_first_read() {
+        let fetcher = MemoizingTagFetcher::new(counting(&node(), &["15", "14", "13"]));
+
+        let mut tags = fetcher.fetch(&node());
+        assert_eq!(fetcher.fetcher.fetches.load(Ordering::SeqCst), 0);
+
+        tags.next();
+        assert_eq!(fetcher.fetcher.fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn shares_errors() {
+        let fetcher = MemoizingTagFetcher::new(counting(&node(), &["15"]));
+        let ubuntu = ImageName::new(None, "ubuntu".to_string());
+
+        let first = fetcher.fetch(&ubuntu).collect::<Vec<_>>();
+        let second = fetcher.fetch(&ubuntu).collect::<Vec<_>>();
+
+        assert_eq!(first.len(), 1);
+        assert!(first[0].is_err());
+        assert_eq!(first, second);
+        assert_eq!(fetcher.fetcher.fetches.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,659 @@
+use std::collections::{HashMap, VecDeque};
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::header::{HeaderMap, ACCEPT, LINK, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::http::{HttpClient, SendError};
+use super::{Tag, TagFetcher, TagRecord};
+use crate::credentials::Credentials;
+use crate::image::{Digest, ImageName};
+
+/// Fetches tags from a registry implementing the
+/// [Registry HTTP API v2](https://docs.docker.com/registry/spec/api/),
+/// e.g. Harbor, GitLab or GHCR.
+///
+/// The API does not expose when a tag was pushed, and registries list
+/// the tags in lexical order. This fetcher therefore yields the tags in
+/// descending order, comparing numbers by their value, e.g. `10.0` before
+/// `9.0`. Since this order is not necessarily antichronological,
+/// `lists_newest_first` is `false`, so that updates are selected by version.
+///
+/// At most `search_limit` tags are listed. Since they are listed in lexical
+/// order, newer tags may be missed if an image has more tags than that.
+#[derive(Debug)]
+pub struct OciTagFetcher {
+    registry_url: String,
+    search_limit: usize,
+    client: HttpClient,
+    credentials: Option<Credentials>,
+}
+
+// API types from the registry
+
+#[derive(Deserialize, Debug)]
+struct TagList {
+    tags: Option<Vec<Tag>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+impl OciTagFetcher {
+    /// Constructs a fetcher for the registry at `registry_url`, e.g. `https://ghcr.io`.
+    pub fn new(registry_url: impl Into<String>) -> Self {
+        Self::with_search_limit(registry_url, 100)
+    }
+
+    pub fn with_search_limit(registry_url: impl Into<String>, search_limit: usize) -> Self {
+        OciTagFetcher {
+            registry_url: registry_url.into().trim_end_matches('/').to_string(),
+            search_limit,
+            client: HttpClient::default(),
+            credentials: None,
+        }
+    }
+
+    /// Sends the requests with `client`, e.g. one built from an `http::HttpConfig`.
+    pub fn with_client(mut self, client: impl Into<HttpClient>) -> Self {
+        self.client = client.into();
+        self
+    }
+
+    /// Authenticates with `credentials` instead of anonymously.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+}
+
+impl TagFetcher for OciTagFetcher {
+    type TagIter = std::iter::Take<OciTagIterator>;
+    type FetchError = OciTagFetcherError;
+
+    fn fetch(&self, name: &ImageName) -> Self::TagIter {
+        OciTagIterator {
+            session: self.session(name),
+            state: State::Unfetched,
+        }
+        .take(self.search_limit)
+    }
+
+    /// Looks up the digest via a `HEAD` request for the tag's manifest.
+    ///
+    /// For multi-platform images, this is the digest of the manifest list.
+    fn digest(&self, name: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        self.session(name).fetch_digest(tag)
+    }
+
+    fn lists_newest_first(&self, _name: &ImageName) -> bool {
+        false
+    }
+}
+
+impl OciTagFetcher {
+    fn session(&self, name: &ImageName) -> Session {
+        Session {
+            registry_url: self.registry_url.clone(),
+            image_name: name.clone(),
+            search_limit: self.search_limit,
+            client: self.client.clone(),
+            credentials: self.credentials.clone(),
+            authorization: None,
+        }
+    }
+}
+
+/// The manifest types whose digest is pinned, preferring manifest lists so that all platforms are covered.
+const MANIFEST_TYPES: &str = "application/vnd.docker.distribution.manifest.list.v2+json, application/vnd.oci.image.index.v1+json, application/vnd.docker.distribution.manifest.v2+json, application/vnd.oci.image.manifest.v1+json";
+
+const FETCH_AMOUNT: usize = 100;
+
+pub struct OciTagIterator {
+    session: Session,
+    state: State,
+}
+
+enum State {
+    Unfetched,
+    Fetched(VecDeque<Tag>),
+    End,
+}
+
+impl Iterator for OciTagIterator {
+    type Item = Result<TagRecord, OciTagFetcherError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.state {
+            State::Unfetched => match self.session.fetch_all() {
+                Ok(mut tags) => {
+                    tags.sort_by(|a, b| compare_numerically(b, a));
+                    let mut tags = VecDeque::from(tags);
+                    let next = tags.pop_front();
+                    self.state = State::Fetched(tags);
+                    next.map(|tag| Ok(tag.into()))
+                }
+                Err(error) => {
+                    self.state = State::End;
+                    Some(Err(error))
+                }
+            },
+            State::Fetched(tags) => tags.pop_front().map(|tag| Ok(tag.into())),
+            State::End => None,
+        }
+    }
+}
+
+struct Session {
+    registry_url: String,
+    image_name: ImageName,
+    search_limit: usize,
+    client: HttpClient,
+    credentials: Option<Credentials>,
+    authorization: Option<Authorization>,
+}
+
+enum Authorization {
+    Basic(Credentials),
+    Bearer(String),
+}
+
+impl Session {
+    /// Lists the image's tags, stopping after the page that reaches the search limit.
+    fn fetch_all(&mut self) -> Result<Vec<Tag>, OciTagFetcherError> {
+        let mut tags = Vec::new();
+        let mut next_url = Some(format!(
+            "{registry}/v2/{image}/tags/list?n={amount}",
+            registry = self.registry_url,
+            image = self.image_name.repository(),
+            amount = FETCH_AMOUNT
+        ));
+
+        while let Some(url) = next_url {
+            log::info!(
+                "Fetching tags for {image}:\n{url}",
+                image = self.image_name,
+                url = url
+            );
+            let response = self.get(&url)?;
+            log::debug!("Received response with status `{}`.", response.status());
+
+            next_url = next_link(response.headers()).map(|link| self.resolve(&link));
+            log::debug!("Reading JSON body...");
+            let page = response.json::<TagList>()?;
+            log::info!("Fetch was successful.");
+            tags.extend(page.tags.unwrap_or_default());
+
+            if tags.len() >= self.search_limit && next_url.is_some() {
+                log::warn!(
+                    "{} has more than {} tags, so newer tags might be missed. Increase the search limit to list them all.",
+                    self.image_name,
+                    self.search_limit
+                );
+                break;
+            }
+        }
+
+        if tags.is_empty() {
+            return Err(OciTagFetcherError::EmptyTags(self.image_name.clone()));
+        }
+
+        Ok(tags)
+    }
+
+    fn fetch_digest(&mut self, tag: &str) -> Result<Option<Digest>, OciTagFetcherError> {
+        let url = format!(
+            "{registry}/v2/{image}/manifests/{tag}",
+            registry = self.registry_url,
+            image = self.image_name.repository(),
+            tag = tag
+        );
+        log::info!(
+            "Fetching digest for {image}:{tag}:\n{url}",
+            image = self.image_name,
+            tag = tag,
+            url = url
+        );
+        let client = self.client.clone();
+        let response = self.send(|| client.head(&url).header(ACCEPT, MANIFEST_TYPES))?;
+        Ok(response
+            .headers()
+            .get("docker-content-digest")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string))
+    }
+
+    fn get(&mut self, url: &str) -> Result<reqwest::blocking::Response, OciTagFetcherError> {
+        let client = self.client.clone();
+        self.send(|| client.get(url))
+    }
+
+    /// Sends the request built by `build`, answering an authentication challenge once if necessary.
+    fn send(
+        &mut self,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<reqwest::blocking::Response, OciTagFetcherError> {
+        let response = self.client.send(self.authorize(build()))?;
+        let response = if response.status() == StatusCode::UNAUTHORIZED {
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            self.authorization = Some(match &self.credentials {
+                Some(credentials) if is_basic_challenge(&challenge) => {
+                    Authorization::Basic(credentials.clone())
+                }
+                _ => Authorization::Bearer(self.request_token(&challenge)?),
+            });
+            self.client.send(self.authorize(build()))?
+        } else {
+            response
+        };
+
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(OciTagFetcherError::UnexpectedStatus(response.status()))
+        }
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.authorization {
+            Some(Authorization::Basic(credentials)) => {
+                request.basic_auth(&credentials.username, Some(&credentials.password))
+            }
+            Some(Authorization::Bearer(token)) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Exchanges a `Bearer` challenge for a token.
+    ///
+    /// The token is requested anonymously, unless there are credentials.
+    ///
+    /// For details, see https://docs.docker.com/registry/spec/auth/token/
+    fn request_token(&self, challenge: &str) -> Result<String, OciTagFetcherError> {
+        let parameters = parse_bearer_challenge(challenge)
+            .ok_or_else(|| OciTagFetcherError::UnsupportedChallenge(challenge.to_string()))?;
+        let realm = parameters
+            .get("realm")
+            .ok_or_else(|| OciTagFetcherError::UnsupportedChallenge(challenge.to_string()))?;
+        let scope = parameters
+            .get("scope")
+            .cloned()
+            .unwrap_or_else(|| format!("repository:{}:pull", self.image_name.repository()));
+        let mut query = vec![("scope", scope)];
+        if let Some(service) = parameters.get("service") {
+            query.push(("service", service.clone()));
+        }
+
+        log::info!("Requesting token from {}...", realm);
+        let mut request = self.client.get(realm).query(&query);
+        if let Some(credentials) = &self.credentials {
+            request = request.basic_auth(&credentials.username, Some(&credentials.password));
+        }
+        let response = self
+            .client
+            .send(request)?
+            .error_for_status()?
+            .json::<TokenResponse>()?;
+        response
+            .token
+            .or(response.access_token)
+            .ok_or(OciTagFetcherError::MissingToken)
+    }
+
+    /// Resolves a link relative to the registry.
+    fn resolve(&self, link: &str) -> String {
+        if link.starts_with('/') {
+            format!("{}{}", self.registry_url, link)
+        } else {
+            link.to_string()
+        }
+    }
+}
+
+/// Compares tags like strings, except that runs of digits are compared by their numeric value.
+fn compare_numerically(a: &str, b: &str) -> std::cmp::Ordering {
+    Chunk::split(a).cmp(Chunk::split(b))
+}
+
+/// A run of digits or of other characters in a tag.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Chunk<'a> {
+    /// Compares by the number of significant digits first, so that `10` is greater than `9`.
+    Number {
+        digits: usize,
+        significant: &'a str,
+    },
+    Text(&'a str),
+}
+
+impl<'a> Chunk<'a> {
+    fn split(tag: &'a str) -> impl Iterator<Item = Chunk<'a>> {
+        let mut rest = tag;
+        std::iter::from_fn(move || {
+            let is_number = rest.chars().next()?.is_ascii_digit();
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() != is_number)
+                .unwrap_or(rest.len());
+            let (chunk, remainder) = rest.split_at(end);
+            rest = remainder;
+            Some(if is_number {
+                let significant = chunk.trim_start_matches('0');
+                Chunk::Number {
+                    digits: significant.len(),
+                    significant,
+                }
+            } else {
+                Chunk::Text(chunk)
+            })
+        })
+    }
+}
+
+/// Extracts the URL of the next page from a header like `Link: </v2/foo/tags/list?last=b&n=2>; rel="next"`.
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let mut parts = link.split(';');
+            let target = parts.next()?.trim();
+            let is_next = parts.any(|parameter| {
+                let parameter = parameter.trim().replace(' ', "");
+                parameter == r#"rel="next""# || parameter == "rel=next"
+            });
+            if is_next {
+                Some(
+                    target
+                        .trim_start_matches('<')
+                        .trim_end_matches('>')
+                        .to_string(),
+                )
+            } else {
+                None
+            }
+        })
+}
+
+fn is_basic_challenge(challenge: &str) -> bool {
+    challenge
+        .trim()
+        .get(..6)
+        .map(|scheme| scheme.eq_ignore_ascii_case("basic "))
+        .unwrap_or(false)
+}
+
+/// Parses a challenge like `Bearer realm="https://auth.example.com/token",service="registry"`.
+fn parse_bearer_challenge(challenge: &str) -> Option<HashMap<String, String>> {
+    let (scheme, parameters) = challenge.trim().split_at(challenge.trim().find(' ')?);
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    let mut parsed = HashMap::new();
+    let mut rest = parameters.trim();
+    while !rest.is_empty() {
+        let equals = rest.find('=')?;
+        let key = rest[..equals].trim().to_lowercase();
+        rest = &rest[equals + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            rest = &quoted[end + 1..];
+            quoted[..end].to_string()
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+            value
+        };
+        parsed.insert(key, value);
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+
+    Some(parsed)
+}
+
+#[derive(Debug, Error)]
+pub enum OciTagFetcherError {
+    #[error(transparent)]
+    FetchError(#[from] reqwest::Error),
+    #[error(transparent)]
+    Send(#[from] SendError),
+    #[error("The registry responded with unexpected status `{0}`")]
+    UnexpectedStatus(StatusCode),
+    #[error("The registry's authentication challenge `{0}` is not supported")]
+    UnsupportedChallenge(String),
+    #[error("The registry's token service did not return a token")]
+    MissingToken,
+    #[error("The tag list was empty (this might indicate that `{0}` is not a valid image name)")]
+    EmptyTags(ImageName),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::tag_fetcher::test::{Response, StandIn};
+
+    fn ubuntu() -> ImageName {
+        ImageName::new(None, "ubuntu".to_string())
+    }
+
+    #[test]
+    fn follows_pagination() {
+        let registry = StandIn::start(|request| match request.path.as_str() {
+            "/v2/library/ubuntu/tags/list?n=100" => {
+                Response::ok(r#"{"name":"ubuntu","tags":["14.04","14.05"]}"#).with_header(
+                    "Link",
+                    r#"</v2/library/ubuntu/tags/list?last=14.05&n=100>; rel="next""#,
+                )
+            }
+            "/v2/library/ubuntu/tags/list?last=14.05&n=100" => {
+                Response::ok(r#"{"name":"ubuntu","tags":["15.02"]}"#)
+            }
+            _ => Response::status(404),
+        });
+
+        let fetcher = OciTagFetcher::new(registry.url());
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["15.02", "14.05", "14.04"]);
+    }
+
+    #[test]
+    fn orders_tags_numerically() {
+        let registry = StandIn::start(|request| match request.path.as_str() {
+            "/v2/library/ubuntu/tags/list?n=100" => {
+                Response::ok(r#"{"name":"ubuntu","tags":["1.10.0","1.9.0","10.0","9.0","latest"]}"#)
+            }
+            _ => Response::status(404),
+        });
+
+        let fetcher = OciTagFetcher::new(registry.url());
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["latest", "10.0", "9.0", "1.10.0", "1.9.0"]);
+        assert!(!fetcher.lists_newest_first(&ubuntu()));
+    }
+
+    #[test]
+    fn stops_paging_at_search_limit() {
+        let registry = StandIn::start(|request| match request.path.as_str() {
+            "/v2/library/ubuntu/tags/list?n=100" => {
+                Response::ok(r#"{"name":"ubuntu","tags":["14.04","14.05"]}"#).with_header(
+                    "Link",
+                    r#"</v2/library/ubuntu/tags/list?last=14.05&n=100>; rel="next""#,
+                )
+            }
+            _ => Response::ok(r#"{"name":"ubuntu","tags":["15.02"]}"#),
+        });
+
+        let fetcher = OciTagFetcher::with_search_limit(registry.url(), 2);
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["14.05", "14.04"]);
+        assert_eq!(registry.requests().len(), 1);
+    }
+
+    #[test]
+    fn answers_bearer_challenge() {
+        let registry = StandIn::start(|request| {
+            if request.path.starts_with("/token") {
+                return Response::ok(r#"{"token":"secret"}"#);
+            }
+            match request.headers.get("authorization").map(String::as_str) {
+                Some("Bearer secret") => Response::ok(r#"{"name":"ubuntu","tags":["14.04"]}"#),
+                _ => Response::status(401).with_header(
+                    "WWW-Authenticate",
+                    r#"Bearer realm="http://{host}/token",service="stand-in""#
+                        .replace("{host}", &request.headers["host"]),
+                ),
+            }
+        });
+
+        let fetcher = OciTagFetcher::new(registry.url());
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["14.04"]);
+
+        let token_request = &registry.requests()[1];
+        assert_eq!(
+            token_request.path,
+            "/token?scope=repository%3Alibrary%2Fubuntu%3Apull&service=stand-in"
+        );
+    }
+
+    #[test]
+    fn requests_token_with_credentials() {
+        let registry = StandIn::start(|request| {
+            if request.path.starts_with("/token") {
+                return match request.headers.get("authorization").map(String::as_str) {
+                    Some("Basic dXNlcjpzZWNyZXQ=") => Response::ok(r#"{"access_token":"private"}"#),
+                    _ => Response::status(403),
+                };
+            }
+            match request.headers.get("authorization").map(String::as_str) {
+                Some("Bearer private") => Response::ok(r#"{"name":"ubuntu","tags":["14.04"]}"#),
+                _ => Response::status(401).with_header(
+                    "WWW-Authenticate",
+                    r#"Bearer realm="http://{host}/token""#
+                        .replace("{host}", &request.headers["host"]),
+                ),
+            }
+        });
+
+        let fetcher = OciTagFetcher::new(registry.url()).with_credentials(Credentials {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+        });
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["14.04"]);
+    }
+
+    #[test]
+    fn answers_basic_challenge_with_credentials() {
+        let registry = StandIn::start(|request| {
+            match request.headers.get("authorization").map(String::as_str) {
+                Some("Basic dXNlcjpzZWNyZXQ=") => {
+                    Response::ok(r#"{"name":"ubuntu","tags":["14.04"]}"#)
+                }
+                _ => Response::status(401)
+                    .with_header("WWW-Authenticate", r#"Basic realm="stand-in""#),
+            }
+        });
+
+        let fetcher = OciTagFetcher::new(registry.url()).with_credentials(Credentials {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+        });
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["14.04"]);
+    }
+
+    #[test]
+    fn respects_search_limit() {
+        let registry = StandIn::start(|_| {
+            Response::ok(r#"{"name":"ubuntu","tags":["14.04","14.05","15.02"]}"#)
+        });
+
+        let fetcher = OciTagFetcher::with_search_limit(registry.url(), 2);
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["15.02", "14.05"]);
+    }
+
+    #[test]
+    fn signals_unexpected_status() {
+        let registry = StandIn::start(|_| Response::status(404));
+
+        let fetcher = OciTagFetcher::new(registry.url());
+        let result = fetcher.fetch(&ubuntu()).next();
+        assert!(matches!(
+            result,
+            Some(Err(OciTagFetcherError::UnexpectedStatus(
+                StatusCode::NOT_FOUND
+            )))
+        ));
+    }
+
+    #[test]
+    fn resolves_digest_of_manifest() {
+        let registry =
+            StandIn::start(
+                |request| match (request.method.as_str(), request.path.as_str()) {
+                    ("HEAD", "/v2/library/ubuntu/manifests/14.04") => Response::status(200)
+                        .with_header("Docker-Content-Digest", "sha256:0123456789abcdef"),
+                    _ => Response::status(404),
+                },
+            );
+
+        let fetcher = OciTagFetcher::new(registry.url());
+        let digest = fetcher.digest(&ubuntu(), "14.04").unwrap();
+        assert_eq!(digest, Some("sha256:0123456789abcdef".to_string()));
+        assert!(registry.requests()[0].headers["accept"]
+            .contains("application/vnd.oci.image.index.v1+json"));
+    }
+
+    #[test]
+    fn parses_bearer_challenge() {
+        let parameters = parse_bearer_challenge(
+            r#"Bearer realm="https://auth.example.com/token",service="registry.example.com",scope="repository:foo:pull""#,
+        )
+        .unwrap();
+        assert_eq!(parameters["realm"], "https://auth.example.com/token");
+        assert_eq!(parameters["service"], "registry.example.com");
+        assert_eq!(parameters["scope"], "repository:foo:pull");
+    }
+
+    #[test]
+    fn rejects_basic_challenge() {
+        assert_eq!(parse_bearer_challenge(r#"Basic realm="registry""#), None);
+    }
+}
@@ -0,0 +1,376 @@
+use std::io::{BufRead, BufReader, Lines, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{TagFetcher, TagRecord};
+use crate::image::{Digest, ImageName};
+
+/// Fetches tags by running an external program, e.g. to support registries with proprietary APIs.
+///
+/// The program is started for every request and receives a single JSON
+/// object on stdin, either
+/// `{"action":"tags","image":"registry.example.com/team/app","registry":"registry.example.com","repository":"team/app","search_limit":100}`
+/// or `{"action":"digest",...,"tag":"1.2.3"}`.
+///
+/// It answers with one JSON object per line on stdout:
+/// - `{"tag":{"name":"1.2.3"}}` for each tag, newest first, optionally with the
+///   other fields of `TagRecord`, like `"last_updated"` or `"digest"`,
+/// - `{"digest":"sha256:..."}` or `{"digest":null}` for the requested digest,
+/// - `{"error":"message"}` if it fails.
+///
+/// The program may stop early once `search_limit` tags were written, and is
+/// killed once uptag has read enough tags. A non-zero exit status is reported
+/// as an error.
+#[derive(Debug, Clone)]
+pub struct PluginTagFetcher {
+    program: PathBuf,
+    args: Vec<String>,
+    search_limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Request<'a> {
+    action: Action,
+    image: String,
+    registry: &'a str,
+    repository: String,
+    search_limit: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Tags,
+    Digest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Message {
+    Tag(TagRecord),
+    Digest(Option<Digest>),
+    Error(String),
+}
+
+impl PluginTagFetcher {
+    /// Runs `program` to fetch tags.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self::with_search_limit(program, 100)
+    }
+
+    pub fn with_search_limit(program: impl Into<PathBuf>, search_limit: usize) -> Self {
+        PluginTagFetcher {
+            program: program.into(),
+            args: Vec::new(),
+            search_limit,
+        }
+    }
+
+    /// Passes `args` to the program, e.g. the script an interpreter runs.
+    pub fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        self.args.extend(args);
+        self
+    }
+
+    fn start(
+        &self,
+        image: &ImageName,
+        action: Action,
+        tag: Option<&str>,
+    ) -> Result<Plugin, PluginError> {
+        let program = self.program.display().to_string();
+        log::info!("Running plugin `{}` for {}...", program, image);
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|error| PluginError::Spawn {
+                program: program.clone(),
+                source: error,
+            })?;
+
+        let request = Request {
+            action,
+            image: image.to_string(),
+            registry: image.registry(),
+            repository: image.repository(),
+            search_limit: self.search_limit,
+            tag,
+        };
+        let mut stdin = child.stdin.take().unwrap(); // Stdin is piped.
+        let written = serde_json::to_writer(&mut stdin, &request)
+            .map_err(std::io::Error::from)
+            .and_then(|_| stdin.write_all(b"\n"))
+            // Plugins that do not need the request may exit without reading it.
+            .or_else(|error| match error.kind() {
+                std::io::ErrorKind::BrokenPipe => Ok(()),
+                _ => Err(error),
+            });
+        // Closing stdin signals the end of the request.
+        drop(stdin);
+        let stdout = child.stdout.take().unwrap(); // Stdout is piped.
+        let plugin = Plugin {
+            program,
+            child,
+            lines: BufReader::new(stdout).lines(),
+        };
+        written.map_err(|error| PluginError::Io {
+            program: plugin.program.clone(),
+            source: error,
+        })?;
+        Ok(plugin)
+    }
+}
+
+impl TagFetcher for PluginTagFetcher {
+    type TagIter = PluginTagIterator;
+    type FetchError = PluginError;
+
+    fn fetch(&self, image: &ImageName) -> Self::TagIter {
+        let (plugin, error) = match self.start(image, Action::Tags, None) {
+            Ok(plugin) => (Some(plugin), None),
+            Err(error) => (None, Some(error)),
+        };
+        PluginTagIterator {
+            plugin,
+            error,
+            remaining: self.search_limit,
+        }
+    }
+
+    fn digest(&self, image: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        let mut plugin = self.start(image, Action::Digest, Some(tag))?;
+        match plugin.next_message()? {
+            Some(Message::Digest(digest)) => {
+                plugin.finish()?;
+                Ok(digest)
+            }
+            Some(Message::Error(message)) => Err(plugin.reported(message)),
+            Some(Message::Tag(_)) | None => Err(plugin.unexpected("a digest")),
+        }
+    }
+}
+
+/// A running plugin.
+struct Plugin {
+    program: String,
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl Plugin {
+    /// Reads the next message, skipping blank lines. Returns `None` at the end of the output.
+    fn next_message(&mut self) -> Result<Option<Message>, PluginError> {
+        loop {
+            let line = match self.lines.next() {
+                Some(line) => line.map_err(|error| PluginError::Io {
+                    program: self.program.clone(),
+                    source: error,
+                })?,
+                None => return Ok(None),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return serde_json::from_str(&line).map(Some).map_err(|error| {
+                PluginError::InvalidOutput {
+                    program: self.program.clone(),
+                    line,
+                    source: error,
+                }
+            });
+        }
+    }
+
+    /// Waits for the plugin to exit, failing if it exited unsuccessfully.
+    fn finish(&mut self) -> Result<(), PluginError> {
+        let status = self.child.wait().map_err(|error| PluginError::Io {
+            program: self.program.clone(),
+            source: error,
+        })?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(PluginError::Failed {
+                program: self.program.clone(),
+                status,
+            })
+        }
+    }
+
+    fn reported(&self, message: String) -> PluginError {
+        PluginError::Reported {
+            program: self.program.clone(),
+            message,
+        }
+    }
+
+    fn unexpected(&self, expected: &'static str) -> PluginError {
+        PluginError::UnexpectedMessage {
+            program: self.program.clone(),
+            expected,
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        // The plugin is no longer needed if enough tags were read or it failed.
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// Reads the tags a plugin writes, ending after the first error.
+pub struct PluginTagIterator {
+    /// Is `None` once the iterator ended.
+    plugin: Option<Plugin>,
+    /// Why the plugin could not be started, which is emitted once.
+    error: Option<PluginError>,
+    remaining: usize,
+}
+
+impl Iterator for PluginTagIterator {
+    type Item = Result<TagRecord, PluginError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+        if self.remaining == 0 {
+            self.plugin = None;
+            return None;
+        }
+        let plugin = self.plugin.as_mut()?;
+
+        let result = match plugin.next_message() {
+            Ok(Some(Message::Tag(tag))) => {
+                self.remaining -= 1;
+                return Some(Ok(tag));
+            }
+            Ok(Some(Message::Error(message))) => Some(Err(plugin.reported(message))),
+            Ok(Some(Message::Digest(_))) => Some(Err(plugin.unexpected("a tag"))),
+            Ok(None) => plugin.finish().err().map(Err),
+            Err(error) => Some(Err(error)),
+        };
+        self.plugin = None;
+        result
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PluginError {
+    #[error("Failed to run plugin `{program}`")]
+    Spawn {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to communicate with plugin `{program}`")]
+    Io {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Plugin `{program}` wrote invalid output `{line}`")]
+    InvalidOutput {
+        program: String,
+        line: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Plugin `{program}` did not respond with {expected}")]
+    UnexpectedMessage {
+        program: String,
+        expected: &'static str,
+    },
+    #[error("Plugin `{program}` failed: {message}")]
+    Reported { program: String, message: String },
+    #[error("Plugin `{program}` exited with {status}")]
+    Failed { program: String, status: ExitStatus },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn app() -> ImageName {
+        ImageName::parse("registry.example.com/team/app").unwrap()
+    }
+
+    fn plugin_script(directory: &tempfile::TempDir, script: &str) -> PluginTagFetcher {
+        let path = directory.path().join("uptag-plugin");
+        std::fs::write(&path, script).unwrap();
+        PluginTagFetcher::with_search_limit("sh", 2).with_args(vec![path.display().to_string()])
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_tags_from_plugin() {
+        let directory = tempfile::tempdir().unwrap();
+        let request = directory.path().join("request.json");
+        let plugin = plugin_script(
+            &directory,
+            &format!(
+                "cat > '{}'\n\
+                 echo '{{\"tag\":{{\"name\":\"1.1\"}}}}'\n\
+                 echo '{{\"tag\":{{\"name\":\"1.0\",\"digest\":\"sha256:10\"}}}}'\n\
+                 echo '{{\"tag\":{{\"name\":\"0.9\"}}}}'\n",
+                request.display()
+            ),
+        );
+
+        let tags = plugin.fetch(&app()).collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(tags, vec!["1.1", "1.0"]);
+        assert_eq!(tags[1].digest, Some("sha256:10".to_string()));
+        let request: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&request).unwrap()).unwrap();
+        assert_eq!(request["action"], "tags");
+        assert_eq!(request["registry"], "registry.example.com");
+        assert_eq!(request["repository"], "team/app");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_digest_via_plugin() {
+        let directory = tempfile::tempdir().unwrap();
+        let plugin = plugin_script(
+            &directory,
+            "cat > /dev/null\necho '{\"digest\":\"sha256:11\"}'\n",
+        );
+
+        let digest = plugin.digest(&app(), "1.1").unwrap();
+        assert_eq!(digest, Some("sha256:11".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_plugin_errors() {
+        let directory = tempfile::tempdir().unwrap();
+        let plugin = plugin_script(&directory, "echo '{\"error\":\"not found\"}'\n");
+
+        let result = plugin.fetch(&app()).next().unwrap();
+        assert!(
+            matches!(result, Err(PluginError::Reported { message, .. }) if message == "not found")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_failing_plugin() {
+        let directory = tempfile::tempdir().unwrap();
+        let plugin = plugin_script(&directory, "exit 3\n");
+
+        let result = plugin.fetch(&app()).collect::<Result<Vec<_>, _>>();
+        assert!(matches!(result, Err(PluginError::Failed { .. })));
+    }
+}
@@ -0,0 +1,370 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::blocking::{RequestBuilder, Response};
+
+use super::http::{HttpClient, SendError};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{StatusCode, Url};
+
+/// Configures how failed requests are retried and how much of a rate limit is left unused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How often a request is repeated after it was rate limited, failed with a server error or timed out.
+    pub max_retries: u32,
+    /// The delay before the first retry, which doubles with each further retry.
+    ///
+    /// A `Retry-After` header sent by the registry takes precedence.
+    pub initial_backoff: Duration,
+    /// The longest delay to wait for a retry or for a rate limit to reset.
+    ///
+    /// If a registry asks to wait longer, the request fails instead.
+    pub max_backoff: Duration,
+    /// How many requests of the rate limit to leave unused.
+    ///
+    /// Once only this many requests remain, fetching pauses until the rate
+    /// limit resets, or fails if that takes longer than `max_backoff`.
+    pub quota_reserve: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            quota_reserve: 0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sends every request only once.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Sends the request built by `build` with `client`, retrying transient failures.
+    ///
+    /// Returns the last response if all retries were used up, so that the caller can classify its status.
+    pub(crate) fn send(
+        &self,
+        client: &HttpClient,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Response, SendError> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            let retries_left = attempt < self.max_retries;
+            attempt += 1;
+            let wait = match client.send(build()) {
+                Ok(response) if retries_left && is_transient(response.status()) => {
+                    match self.retry_delay(
+                        response.url(),
+                        response.status(),
+                        response.headers(),
+                        backoff,
+                    ) {
+                        Some(wait) => wait,
+                        None => return Ok(response),
+                    }
+                }
+                Err(error) if retries_left && (error.is_timeout() || error.is_connect()) => {
+                    log::warn!("{}, retrying in {}s...", error, backoff.as_secs());
+                    backoff
+                }
+                result => return result,
+            };
+            std::thread::sleep(wait);
+            backoff = std::cmp::min(backoff * 2, self.max_backoff);
+        }
+    }
+
+    /// Like `send`, but sends the request built by `build` without blocking.
+    #[cfg(feature = "async")]
+    pub(crate) async fn send_async(
+        &self,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            let retries_left = attempt < self.max_retries;
+            attempt += 1;
+            let wait = match build().send().await {
+                Ok(response) if retries_left && is_transient(response.status()) => {
+                    match self.retry_delay(
+                        response.url(),
+                        response.status(),
+                        response.headers(),
+                        backoff,
+                    ) {
+                        Some(wait) => wait,
+                        None => return Ok(response),
+                    }
+                }
+                Err(error) if retries_left && (error.is_timeout() || error.is_connect()) => {
+                    log::warn!("{}, retrying in {}s...", error, backoff.as_secs());
+                    backoff
+                }
+                result => return result,
+            };
+            tokio::time::sleep(wait).await;
+            backoff = std::cmp::min(backoff * 2, self.max_backoff);
+        }
+    }
+
+    /// How long to wait before retrying a request to `url` that failed with `status`.
+    ///
+    /// Returns `None` if the registry asks to wait longer than `max_backoff`.
+    fn retry_delay(
+        &self,
+        url: &Url,
+        status: StatusCode,
+        headers: &HeaderMap,
+        backoff: Duration,
+    ) -> Option<Duration> {
+        let wait = retry_after(headers).unwrap_or(backoff);
+        if wait > self.max_backoff {
+            return None;
+        }
+        log::warn!(
+            "Request to {} failed with status `{}`, retrying in {}s...",
+            url,
+            status,
+            wait.as_secs()
+        );
+        Some(wait)
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Reads the `Retry-After` header, which is either a number of seconds or an HTTP date.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::from_secs(0)),
+    )
+}
+
+/// Tracks the rate limit a registry reports in its response headers.
+///
+/// DockerHub reports `ratelimit-remaining: 76;w=21600` on its registry and
+/// `x-ratelimit-remaining` and `x-ratelimit-reset` on its API.
+#[derive(Debug, Default)]
+pub(crate) struct Quota {
+    state: Mutex<QuotaState>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct QuotaState {
+    remaining: Option<u32>,
+    reset: Option<SystemTime>,
+}
+
+/// The rate limit would be exceeded before it resets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QuotaExhausted {
+    /// How long until the rate limit resets, if known.
+    pub reset_in: Option<Duration>,
+}
+
+impl Quota {
+    pub fn update(&self, headers: &HeaderMap) {
+        let header = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| headers.get(*name))
+                .and_then(|value| value.to_str().ok())
+                // Drops parameters like the window in `76;w=21600`.
+                .and_then(|value| value.split(';').next())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        };
+        let remaining = header(&["x-ratelimit-remaining", "ratelimit-remaining"]);
+        let reset = header(&["x-ratelimit-reset"]);
+
+        if remaining.is_some() || reset.is_some() {
+            let mut state = self.state.lock().unwrap();
+            state.remaining = remaining.map(|remaining| remaining as u32);
+            state.reset = reset.map(|reset| UNIX_EPOCH + Duration::from_secs(reset));
+        }
+    }
+
+    /// Waits until another request can be sent without using up the reserve of the rate limit.
+    pub fn acquire(&self, policy: &RetryPolicy) -> Result<(), QuotaExhausted> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(wait) = state.reserve(policy)? {
+            // Holding the lock pauses all other fetches as well.
+            std::thread::sleep(wait);
+            *state = QuotaState::default();
+        }
+        Ok(())
+    }
+
+    /// Like `acquire`, but waits without blocking.
+    ///
+    /// Other fetches are not paused while waiting, but they wait for the reset themselves.
+    #[cfg(feature = "async")]
+    pub async fn acquire_async(&self, policy: &RetryPolicy) -> Result<(), QuotaExhausted> {
+        let wait = self.state.lock().unwrap().reserve(policy)?;
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+            *self.state.lock().unwrap() = QuotaState::default();
+        }
+        Ok(())
+    }
+}
+
+impl QuotaState {
+    /// Accounts for another request, returning how long to wait for the rate limit to reset first.
+    fn reserve(&mut self, policy: &RetryPolicy) -> Result<Option<Duration>, QuotaExhausted> {
+        match self.remaining {
+            Some(remaining) if remaining <= policy.quota_reserve => {
+                let reset_in = self
+                    .reset
+                    .map(|reset| reset.duration_since(SystemTime::now()).unwrap_or_default());
+                match reset_in {
+                    Some(wait) if wait <= policy.max_backoff => {
+                        log::warn!(
+                            "The rate limit is almost exhausted, pausing for {}s until it resets...",
+                            wait.as_secs()
+                        );
+                        Ok(Some(wait))
+                    }
+                    _ => Err(QuotaExhausted { reset_in }),
+                }
+            }
+            Some(remaining) => {
+                // Accounts for this request until the registry reports the new count.
+                self.remaining = Some(remaining - 1);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use reqwest::header::HeaderValue;
+
+    use crate::tag_fetcher::test::{Response as StandInResponse, StandIn};
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_secs(1),
+            quota_reserve: 0,
+        }
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        let attempts = Mutex::new(0);
+        let server = StandIn::start(move |_| {
+            let mut attempts = attempts.lock().unwrap();
+            *attempts += 1;
+            match *attempts {
+                1 => StandInResponse::status(429).with_header("Retry-After", "0"),
+                2 => StandInResponse::status(503),
+                _ => StandInResponse::ok("done"),
+            }
+        });
+        let client = HttpClient::default();
+
+        let response = fast_policy()
+            .send(&client, || client.get(server.url().as_str()))
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let server = StandIn::start(|_| StandInResponse::status(500));
+        let client = HttpClient::default();
+
+        let response = fast_policy()
+            .send(&client, || client.get(server.url().as_str()))
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn does_not_wait_longer_than_max_backoff() {
+        let server =
+            StandIn::start(|_| StandInResponse::status(429).with_header("Retry-After", "3600"));
+        let client = HttpClient::default();
+
+        let response = fast_policy()
+            .send(&client, || client.get(server.url().as_str()))
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let server = StandIn::start(|_| StandInResponse::status(404));
+        let client = HttpClient::default();
+
+        let response = fast_policy()
+            .send(&client, || client.get(server.url().as_str()))
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn parses_ratelimit_headers() {
+        let quota = Quota::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "ratelimit-remaining",
+            HeaderValue::from_static("76;w=21600"),
+        );
+
+        quota.update(&headers);
+
+        assert_eq!(quota.state.lock().unwrap().remaining, Some(76));
+    }
+
+    #[test]
+    fn aborts_before_exhausting_quota() {
+        let quota = Quota::default();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("1"));
+        let reset =
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_secs(3600);
+        headers.insert(
+            "x-ratelimit-reset",
+            HeaderValue::from_str(&reset.as_secs().to_string()).unwrap(),
+        );
+        quota.update(&headers);
+
+        assert_eq!(quota.acquire(&fast_policy()), Ok(()));
+        assert!(matches!(
+            quota.acquire(&fast_policy()),
+            Err(QuotaExhausted { reset_in: Some(_) })
+        ));
+    }
+}
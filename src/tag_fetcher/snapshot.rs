@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{TagFetcher, TagRecord};
+use crate::image::{Digest, ImageName};
+use crate::json_file;
+
+/// Serves tags from a snapshot instead of a registry, e.g. for machines without network access.
+///
+/// Snapshots can be created with `uptag fetch --save <file>`.
+#[derive(Debug, Default)]
+pub struct SnapshotTagFetcher {
+    snapshot: Snapshot,
+}
+
+impl SnapshotTagFetcher {
+    pub fn new(snapshot: Snapshot) -> Self {
+        SnapshotTagFetcher { snapshot }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, SnapshotError> {
+        Ok(Self::new(Snapshot::from_path(path)?))
+    }
+}
+
+impl TagFetcher for SnapshotTagFetcher {
+    type TagIter = Vec<Result<TagRecord, Self::FetchError>>;
+    type FetchError = SnapshotTagFetcherError;
+
+    fn fetch(&self, image: &ImageName) -> Self::TagIter {
+        match self.snapshot.tags(image) {
+            Some(tags) => tags.iter().cloned().map(Ok).collect(),
+            None => vec![Err(SnapshotTagFetcherError::MissingImage(image.clone()))],
+        }
+    }
+
+    /// Serves the digest recorded with the tag, if any.
+    fn digest(&self, image: &ImageName, tag: &str) -> Result<Option<Digest>, Self::FetchError> {
+        let tags = self
+            .snapshot
+            .tags(image)
+            .ok_or_else(|| SnapshotTagFetcherError::MissingImage(image.clone()))?;
+        Ok(tags
+            .iter()
+            .find(|record| record.name == tag)
+            .and_then(|record| record.digest.clone()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotTagFetcherError {
+    #[error("The snapshot does not contain tags for `{0}`")]
+    MissingImage(ImageName),
+}
+
+/// The tags of several images, ordered from newest to oldest, including their metadata.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    images: BTreeMap<String, Vec<TagRecord>>,
+}
+
+impl Snapshot {
+    /// Loads the snapshot at `path`, treating a missing file as an empty snapshot.
+    pub fn load_or_default(path: &Path) -> Result<Self, SnapshotError> {
+        json_file::load_or_default(path, "snapshot")
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, SnapshotError> {
+        json_file::from_path(path, "snapshot")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        json_file::save(self, path, "snapshot")
+    }
+
+    /// Replaces the tags stored for `image`.
+    pub fn insert(&mut self, image: &ImageName, tags: Vec<TagRecord>) {
+        self.images.insert(image.to_string(), tags);
+    }
+
+    pub fn tags(&self, image: &ImageName) -> Option<&[TagRecord]> {
+        self.images.get(&image.to_string()).map(Vec::as_slice)
+    }
+}
+
+pub type SnapshotError = json_file::Error;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ubuntu() -> ImageName {
+        ImageName::new(None, "ubuntu".to_string())
+    }
+
+    #[test]
+    fn serves_tags_from_snapshot() {
+        let mut snapshot = Snapshot::default();
+        snapshot.insert(&ubuntu(), vec!["20.04".into(), "18.04".into()]);
+        let fetcher = SnapshotTagFetcher::new(snapshot);
+
+        let tags = fetcher
+            .fetch(&ubuntu())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(tags, vec!["20.04", "18.04"]);
+    }
+
+    #[test]
+    fn fails_for_missing_image() {
+        let fetcher = SnapshotTagFetcher::default();
+
+        let result = fetcher
+            .fetch(&ubuntu())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>();
+        assert!(matches!(
+            result,
+            Err(SnapshotTagFetcherError::MissingImage(image)) if image == ubuntu()
+        ));
+    }
+
+    #[test]
+    fn round_trips_through_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("snapshot.json");
+        let registry_image = ImageName::parse("ghcr.io/org/app").unwrap();
+
+        let mut snapshot = Snapshot::load_or_default(&path).unwrap();
+        snapshot.insert(&ubuntu(), vec!["20.04".into()]);
+        snapshot.insert(&registry_image, vec!["1.2".into()]);
+        snapshot.save(&path).unwrap();
+
+        assert_eq!(Snapshot::from_path(&path).unwrap(), snapshot);
+    }
+}
@@ -1,12 +1,27 @@
 use itertools::Itertools;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 use crate::pattern;
 use crate::pattern::Pattern;
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
+/// The numbers of a tag, and its pre-release qualifier if it has one.
+///
+/// Pre-releases order below the release with the same numbers, e.g. `3.10.0rc2 < 3.10.0`.
+/// The numbers of named groups are compared after the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Version {
     parts: Vec<VersionPart>,
+    pre_release: Option<PreRelease>,
+    groups: Vec<VersionGroup>,
+}
+
+/// The numbers of a named group in the pattern, like the Alpine version in `<>.<>-alpine(alpine:<!>.<>)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionGroup {
+    pub name: String,
+    pub parts: Vec<VersionPart>,
+    pub breaking_degree: usize,
 }
 
 type VersionPart = usize;
@@ -16,36 +31,261 @@ impl Version {
         if parts.is_empty() {
             None
         } else {
-            Some(Version { parts })
+            Some(Version {
+                parts,
+                pre_release: None,
+                groups: Vec::new(),
+            })
         }
     }
 
+    pub fn with_pre_release(mut self, pre_release: Option<PreRelease>) -> Self {
+        self.pre_release = pre_release;
+        self
+    }
+
+    pub fn pre_release(&self) -> Option<&PreRelease> {
+        self.pre_release.as_ref()
+    }
+
+    pub fn is_pre_release(&self) -> bool {
+        self.pre_release.is_some()
+    }
+
+    pub fn with_groups(mut self, groups: Vec<VersionGroup>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    pub fn groups(&self) -> &[VersionGroup] {
+        &self.groups
+    }
+
+    /// Breaking if any group has a breaking change, where `breaking_degree` applies to the numbers outside of named groups.
     pub fn update_type(&self, other: &Self, breaking_degree: usize) -> UpdateType {
-        if self.sameness_degree_with(other) >= breaking_degree {
+        if self
+            .changes(other, breaking_degree)
+            .iter()
+            .any(|change| change.update_type == UpdateType::Breaking)
+        {
+            UpdateType::Breaking
+        } else {
+            UpdateType::Compatible
+        }
+    }
+
+    /// The name of the group that determines the update type: the first group with a
+    /// breaking change, or else the first group that changed.
+    ///
+    /// Returns `None` if those are the numbers outside of named groups or if nothing changed.
+    pub fn updated_group(&self, other: &Self, breaking_degree: usize) -> Option<&str> {
+        self.deciding_change(other, breaking_degree)
+            .and_then(|change| change.group)
+    }
+
+    /// The position of the first number that differs from `other` in the group that determines the update type, see `updated_group`.
+    ///
+    /// Returns `None` if nothing changed.
+    pub fn grade(&self, other: &Self, breaking_degree: usize) -> Option<Grade> {
+        self.deciding_change(other, breaking_degree)
+            .map(|change| change.grade)
+    }
+
+    /// The name of the first named group whose numbers are lower than in `other`.
+    ///
+    /// Such a version is not an update, even if its other numbers are higher.
+    pub fn downgraded_group(&self, other: &Self) -> Option<&str> {
+        self.groups
+            .iter()
+            .zip(other.groups.iter())
+            .find(|(own, other)| own.parts < other.parts)
+            .map(|(own, _)| own.name.as_str())
+    }
+
+    fn deciding_change(&self, other: &Self, breaking_degree: usize) -> Option<Change<'_>> {
+        let changes = self.changes(other, breaking_degree);
+        let breaking = changes
+            .iter()
+            .position(|change| change.update_type == UpdateType::Breaking);
+        changes.into_iter().nth(breaking.unwrap_or(0))
+    }
+
+    /// The changes of the groups whose numbers differ from `other`.
+    ///
+    /// The numbers outside of named groups come first and have no name.
+    fn changes(&self, other: &Self, breaking_degree: usize) -> Vec<Change<'_>> {
+        let unnamed = if self.parts != other.parts {
+            Some(Change::new(
+                None,
+                &self.parts,
+                &other.parts,
+                breaking_degree,
+            ))
+        } else if self.pre_release != other.pre_release {
+            Some(Change {
+                group: None,
+                update_type: UpdateType::Compatible,
+                grade: Grade::PreRelease,
+            })
+        } else {
+            None
+        };
+        let named = self
+            .groups
+            .iter()
+            .zip(other.groups.iter())
+            .filter(|(own, other)| own.parts != other.parts)
+            .map(|(own, other)| {
+                Change::new(
+                    Some(own.name.as_str()),
+                    &own.parts,
+                    &other.parts,
+                    own.breaking_degree,
+                )
+            });
+        unnamed.into_iter().chain(named).collect()
+    }
+}
+
+/// How the numbers of a group changed.
+struct Change<'a> {
+    group: Option<&'a str>,
+    update_type: UpdateType,
+    grade: Grade,
+}
+
+impl<'a> Change<'a> {
+    fn new(
+        group: Option<&'a str>,
+        own: &[VersionPart],
+        other: &[VersionPart],
+        breaking_degree: usize,
+    ) -> Self {
+        let sameness_degree = sameness_degree(own, other);
+        let update_type = if sameness_degree >= breaking_degree {
             UpdateType::Compatible
         } else {
             UpdateType::Breaking
+        };
+        Change {
+            group,
+            update_type,
+            grade: Grade::Part(sameness_degree),
         }
     }
+}
 
-    fn sameness_degree_with(&self, other: &Self) -> usize {
+fn sameness_degree(own: &[VersionPart], other: &[VersionPart]) -> usize {
+    own.iter()
+        .zip(other.iter())
+        .take_while(|(l, r)| l == r)
+        .count()
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        use std::cmp::Ordering::*;
         self.parts
-            .iter()
-            .zip(other.parts.iter())
-            .take_while(|(l, r)| l == r)
-            .count()
+            .cmp(&other.parts)
+            .then_with(|| match (&self.pre_release, &other.pre_release) {
+                (None, None) => Equal,
+                // The release follows its pre-releases.
+                (None, Some(_)) => Greater,
+                (Some(_), None) => Less,
+                (Some(own), Some(other)) => own.cmp(other),
+            })
+            .then_with(|| {
+                let group_parts = |version: &Version| {
+                    version
+                        .groups
+                        .iter()
+                        .map(|group| group.parts.clone())
+                        .collect::<Vec<_>>()
+                };
+                group_parts(self).cmp(&group_parts(other))
+            })
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// A pre-release qualifier like `rc2`, ordered by its stage and then by its number.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub struct PreRelease {
+    pub stage: Stage,
+    pub number: VersionPart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+pub enum Stage {
+    Alpha,
+    Beta,
+    ReleaseCandidate,
+}
+
+impl std::str::FromStr for Stage {
+    type Err = String;
+
+    /// Parses the qualifiers `alpha`, `beta` and `rc`, as well as the short forms `a` and `b`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alpha" | "a" => Ok(Stage::Alpha),
+            "beta" | "b" => Ok(Stage::Beta),
+            "rc" => Ok(Stage::ReleaseCandidate),
+            _ => Err(format!("`{}` is not a pre-release qualifier", s)),
+        }
+    }
+}
+
+/// Compatible updates order before breaking ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum UpdateType {
     Compatible,
     Breaking,
 }
 
+/// How far an update reaches, by the position of the first number that changed.
+///
+/// For example, `1.2.3 -> 1.3.0` is a minor update, i.e. `Grade::Part(1)`.
+/// Grades order by position, so that smaller steps are greater.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Grade {
+    /// The number at this position (counting from `0`) is the first that changed.
+    Part(usize),
+    /// Only the pre-release qualifier changed, e.g. from `3.10.0rc1` to `3.10.0rc2` or `3.10.0`.
+    PreRelease,
+}
+
+impl std::fmt::Display for Grade {
+    /// Names the first three positions like semantic versioning, e.g. `minor`, and the others by number, e.g. `part 4`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Grade::Part(0) => write!(f, "major"),
+            Grade::Part(1) => write!(f, "minor"),
+            Grade::Part(2) => write!(f, "patch"),
+            Grade::Part(position) => write!(f, "part {}", position + 1),
+            Grade::PreRelease => write!(f, "pre-release"),
+        }
+    }
+}
+
 pub mod extractor {
     use super::*;
 
+    const PRE_RELEASE_STAGE: &str = "pre_release_stage";
+    const PRE_RELEASE_NUMBER: &str = "pre_release_number";
+    /// The prefix of the groups capturing pinned wildcards, which are numbered.
+    const PINNED: &str = "pinned_wildcard";
+    /// The prefix of the groups capturing the version parts of named groups, which are named `group<index>_<number>`.
+    const GROUP: &str = "group";
+    const WILDCARD: &str = r"[[:alnum:]_.]+";
+
     #[derive(Debug, Clone)]
     pub struct VersionExtractor {
         pattern: Pattern,
@@ -86,19 +326,90 @@ pub mod extractor {
             VersionExtractor { pattern, regex }
         }
 
+        /// Builds a regex with a capture group for each version part, in the order of the parts.
+        ///
+        /// Pinned wildcards, pre-release qualifiers and the version parts of named groups are captured in named groups.
         pub fn regex_for_pattern(pattern: &Pattern) -> Regex {
+            let mut counters = CaptureCounters::default();
+            let raw_regex = format!(
+                "^{}$",
+                Self::regex_for_parts(pattern.parts(), &mut counters, None)
+            );
+
+            Regex::new(&raw_regex).unwrap()
+        }
+
+        fn regex_for_parts(
+            parts: &[pattern::PatternPart],
+            counters: &mut CaptureCounters,
+            group: Option<usize>,
+        ) -> String {
             use pattern::PatternPart::*;
-            let inner_regex = pattern
-                .parts()
+            parts
                 .iter()
                 .map(|part| match part {
-                    Literal(literal) => Self::escape_literal(&literal),
-                    VersionPart => r"(\d+)".to_string(),
+                    Literal(literal) => Self::escape_literal(literal),
+                    VersionPart => match group {
+                        Some(group) => {
+                            counters.group_parts += 1;
+                            format!(r"(?P<{}{}_{}>\d+)", GROUP, group, counters.group_parts)
+                        }
+                        None => r"(\d+)".to_string(),
+                    },
+                    PreRelease => format!(
+                        r"(?P<{}>alpha|beta|rc|a|b)\.?(?P<{}>\d+)",
+                        PRE_RELEASE_STAGE, PRE_RELEASE_NUMBER
+                    ),
+                    Wildcard { pinned: false } => format!("(?:{})", WILDCARD),
+                    Wildcard { pinned: true } => {
+                        counters.pinned += 1;
+                        format!("(?P<{}{}>{})", PINNED, counters.pinned, WILDCARD)
+                    }
+                    Optional(parts) => {
+                        format!("(?:{})?", Self::regex_for_parts(parts, counters, group))
+                    }
+                    Group { parts, .. } => {
+                        let group = counters.groups;
+                        counters.groups += 1;
+                        Self::regex_for_parts(parts, counters, Some(group))
+                    }
+                    Alternatives(spellings) => format!(
+                        "(?:{})",
+                        spellings
+                            .iter()
+                            .map(|spelling| Self::escape_literal(spelling))
+                            .join("|")
+                    ),
                 })
-                .join("");
-            let raw_regex = format!("^{}$", inner_regex);
+                .join("")
+        }
 
-            Regex::new(&raw_regex).unwrap()
+        /// The values of the pinned wildcards, which candidates have to keep to be updates.
+        ///
+        /// A wildcard in an optional group that the tag omits has no value.
+        pub fn pinned_values<T>(&self, candidate: T) -> Option<Vec<Option<String>>>
+        where
+            T: Tagged,
+        {
+            let captures = self.regex.captures(candidate.tag())?;
+            let values = self
+                .regex
+                .capture_names()
+                .flatten()
+                .filter(|name| name.starts_with(PINNED))
+                .map(|name| captures.name(name).map(|value| value.as_str().to_string()))
+                .collect();
+            Some(values)
+        }
+
+        fn extract_pre_release(captures: &regex::Captures) -> Option<PreRelease> {
+            let stage = captures.name(PRE_RELEASE_STAGE)?.as_str().parse().unwrap();
+            let number = captures
+                .name(PRE_RELEASE_NUMBER)?
+                .as_str()
+                .parse::<VersionPart>()
+                .unwrap();
+            Some(PreRelease { stage, number })
         }
 
         fn escape_literal(literal: &str) -> String {
@@ -128,24 +439,58 @@ pub mod extractor {
         where
             T: Tagged,
         {
-            let tag = candidate.tag().as_ref();
-            let parts = self
-                .regex
-                .captures(tag) // Only look at the first match.
+            let tag = candidate.tag();
+            let captures = self.regex.captures(tag)?; // Only look at the first match.
+            let mut parts = Vec::new();
+            let mut groups = self
+                .pattern
+                .groups()
                 .into_iter()
-                .flat_map(|captures| {
-                    captures
-                        .iter()
-                        .skip(1) // We are only interested in the capture groups, so we skip the first submatch, since that contains the entire match.
-                        .filter_map(|maybe_submatch| {
-                            maybe_submatch
-                                .map(|submatch| submatch.as_str().parse::<VersionPart>().unwrap())
-                        })
-                        .collect::<Vec<_>>()
+                .map(|(name, breaking_degree)| VersionGroup {
+                    name: name.to_string(),
+                    parts: Vec::new(),
+                    breaking_degree,
                 })
-                .collect();
-            Version::new(parts)
+                .collect::<Vec<_>>();
+            // We are only interested in the capture groups, so we skip the first submatch, since that contains the entire match.
+            let submatches = captures.iter().zip(self.regex.capture_names()).skip(1);
+            for (maybe_submatch, name) in submatches {
+                // Version parts in optional groups that the tag omits count as zero.
+                let part = || {
+                    maybe_submatch
+                        .map(|submatch| submatch.as_str().parse::<VersionPart>().unwrap())
+                        .unwrap_or(0)
+                };
+                match name {
+                    // Only the version parts outside of named groups are unnamed.
+                    None => parts.push(part()),
+                    Some(name) => {
+                        if let Some(group) = Self::group_index(name) {
+                            groups[group].parts.push(part());
+                        }
+                    }
+                }
+            }
+            Version::new(parts).map(|version| {
+                version
+                    .with_pre_release(Self::extract_pre_release(&captures))
+                    .with_groups(groups)
+            })
         }
+
+        /// The index of the named group whose version part is captured under `capture_name`, if any.
+        fn group_index(capture_name: &str) -> Option<usize> {
+            let (index, _) = capture_name.strip_prefix(GROUP)?.split_once('_')?;
+            index.parse().ok()
+        }
+    }
+
+    /// Numbers the named capture groups, since their names have to be unique.
+    #[derive(Default)]
+    struct CaptureCounters {
+        pinned: usize,
+        groups: usize,
+        group_parts: usize,
     }
 
     #[cfg(test)]
@@ -173,9 +518,7 @@ pub mod extractor {
         {
             fn from(other: S) -> Self {
                 let other = other.borrow();
-                Version {
-                    parts: vec![other.0, other.1, other.2],
-                }
+                Version::new(vec![other.0, other.1, other.2]).unwrap()
             }
         }
 
@@ -237,6 +580,186 @@ pub mod extractor {
             }
         }
 
+        #[test]
+        fn extracts_omitted_parts_as_zero() {
+            let extractor = VersionExtractor::parse("<!>.<>[.<>]").unwrap();
+            assert_eq!(extractor.extract_from("3.9"), Version::new(vec![3, 9, 0]));
+            assert_eq!(extractor.extract_from("3.9.1"), Version::new(vec![3, 9, 1]));
+            assert!(!extractor.matches("3.9."));
+        }
+
+        #[test]
+        fn matches_alternative_spellings() {
+            let extractor = VersionExtractor::parse("<>.<>-{slim|slim-bullseye}").unwrap();
+            assert!(extractor.matches("3.9-slim"));
+            assert!(extractor.matches("3.9-slim-bullseye"));
+            assert!(!extractor.matches("3.9-slim-buster"));
+            assert!(!extractor.matches("3.9-slimXbullseye"));
+        }
+
+        #[test]
+        fn extracts_pre_releases() {
+            let extractor = VersionExtractor::parse("<>.<>.<>[-<pre>]").unwrap();
+            let pre_release = |stage, number| {
+                Version::new(vec![1, 5, 0])
+                    .map(|version| version.with_pre_release(Some(PreRelease { stage, number })))
+            };
+            assert_eq!(
+                extractor.extract_from("1.5.0-beta.3"),
+                pre_release(Stage::Beta, 3)
+            );
+            assert_eq!(
+                extractor.extract_from("1.5.0-alpha1"),
+                pre_release(Stage::Alpha, 1)
+            );
+            assert_eq!(
+                extractor.extract_from("1.5.0-rc2"),
+                pre_release(Stage::ReleaseCandidate, 2)
+            );
+            assert_eq!(extractor.extract_from("1.5.0"), Version::new(vec![1, 5, 0]));
+            assert!(!extractor.matches("1.5.0-beta"));
+            assert!(!extractor.matches("1.5.0-dev1"));
+        }
+
+        #[test]
+        fn orders_pre_releases_below_release() {
+            let extractor = VersionExtractor::parse("<>.<>.<>[<pre>]").unwrap();
+            let ordered = [
+                "3.9.9",
+                "3.10.0a1",
+                "3.10.0a2",
+                "3.10.0b1",
+                "3.10.0rc1",
+                "3.10.0rc2",
+                "3.10.0",
+                "3.10.1a1",
+            ]
+            .iter()
+            .map(|tag| extractor.extract_from(tag).unwrap())
+            .collect::<Vec<_>>();
+            for pair in ordered.windows(2) {
+                assert!(pair[0] < pair[1], "{:?} < {:?}", pair[0], pair[1]);
+            }
+            assert_eq!(
+                ordered[5].update_type(&ordered[6], 1),
+                UpdateType::Compatible
+            );
+        }
+
+        #[test]
+        fn ignores_wildcards_when_extracting() {
+            let extractor = VersionExtractor::parse("<>.<>.<>-alpine<*>").unwrap();
+            assert_eq!(
+                extractor.extract_from("14.5.0-alpine3.12"),
+                Version::new(vec![14, 5, 0])
+            );
+            assert_eq!(
+                extractor.extract_from("14.5.0-alpine3.13"),
+                Version::new(vec![14, 5, 0])
+            );
+            assert!(!extractor.matches("14.5.0-alpine"));
+            assert!(!extractor.matches("14.5.0-alpine3.13-slim"));
+        }
+
+        #[test]
+        fn captures_pinned_wildcards() {
+            let extractor = VersionExtractor::parse("<>.<>-<=>[-<=>]-<*>").unwrap();
+            assert_eq!(
+                extractor.extract_from("7.4-apache-bullseye-slim"),
+                Version::new(vec![7, 4])
+            );
+            assert_eq!(
+                extractor.pinned_values("7.4-apache-bullseye-slim"),
+                Some(vec![
+                    Some("apache".to_string()),
+                    Some("bullseye".to_string())
+                ])
+            );
+            assert_eq!(
+                extractor.pinned_values("7.4-fpm-slim"),
+                Some(vec![Some("fpm".to_string()), None])
+            );
+            assert_eq!(extractor.pinned_values("7.4"), None);
+        }
+
+        #[test]
+        fn extracts_named_groups() {
+            let extractor = VersionExtractor::parse("<!>.<>.<>-alpine(alpine:<!>.<>)").unwrap();
+            let version = extractor.extract_from("1.21.6-alpine3.16").unwrap();
+            assert_eq!(
+                version,
+                Version::new(vec![1, 21, 6])
+                    .unwrap()
+                    .with_groups(vec![VersionGroup {
+                        name: "alpine".to_string(),
+                        parts: vec![3, 16],
+                        breaking_degree: 1
+                    }])
+            );
+        }
+
+        #[test]
+        fn evaluates_each_group() {
+            let extractor = VersionExtractor::parse("<!>.<>.<>-alpine(alpine:<!>.<>)").unwrap();
+            let current = extractor.extract_from("1.21.6-alpine3.16").unwrap();
+            let version = |tag| extractor.extract_from(tag).unwrap();
+
+            let app_update = version("1.22.0-alpine3.16");
+            assert_eq!(app_update.update_type(&current, 1), UpdateType::Compatible);
+            assert_eq!(app_update.updated_group(&current, 1), None);
+
+            let alpine_update = version("1.21.6-alpine3.17");
+            assert!(alpine_update > current);
+            assert_eq!(alpine_update.grade(&current, 1), Some(Grade::Part(1)));
+            assert_eq!(
+                alpine_update.update_type(&current, 1),
+                UpdateType::Compatible
+            );
+            assert_eq!(alpine_update.updated_group(&current, 1), Some("alpine"));
+
+            let alpine_break = version("1.22.0-alpine4.0");
+            assert_eq!(alpine_break.update_type(&current, 1), UpdateType::Breaking);
+            assert_eq!(alpine_break.updated_group(&current, 1), Some("alpine"));
+
+            let both_break = version("2.0.0-alpine4.0");
+            assert_eq!(both_break.update_type(&current, 1), UpdateType::Breaking);
+            assert_eq!(both_break.updated_group(&current, 1), None);
+
+            let alpine_downgrade = version("1.21.7-alpine2.0");
+            assert_eq!(alpine_downgrade.downgraded_group(&current), Some("alpine"));
+            assert_eq!(app_update.downgraded_group(&current), None);
+        }
+
+        #[test]
+        fn grades_updates() {
+            let extractor = VersionExtractor::parse("<!>.<>.<>.<>[<pre>]").unwrap();
+            let current = extractor.extract_from("3.9.1.0").unwrap();
+            let grade = |tag| extractor.extract_from(tag).unwrap().grade(&current, 1);
+
+            assert_eq!(grade("4.0.0.0"), Some(Grade::Part(0)));
+            assert_eq!(grade("3.10.0.0"), Some(Grade::Part(1)));
+            assert_eq!(grade("3.9.2.0"), Some(Grade::Part(2)));
+            assert_eq!(grade("3.9.1.1"), Some(Grade::Part(3)));
+            assert_eq!(grade("3.9.1.0rc1"), Some(Grade::PreRelease));
+            assert_eq!(grade("3.9.1.0"), None);
+            assert!(Grade::Part(2) > Grade::Part(1));
+
+            let names = [
+                Grade::Part(0),
+                Grade::Part(1),
+                Grade::Part(2),
+                Grade::Part(3),
+                Grade::PreRelease,
+            ]
+            .iter()
+            .map(Grade::to_string)
+            .collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                vec!["major", "minor", "patch", "part 4", "pre-release"]
+            );
+        }
+
         // Comparison
 
         prop_compose! {